@@ -0,0 +1,133 @@
+use bevy::prelude::*;
+use bevy_platformer::{
+    physics::{Position, AABB},
+    quadtree::Quadtree,
+};
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+const SIZES: [usize; 3] = [1_000, 10_000, 100_000];
+const CAPACITY: usize = 4;
+const QUERIES: usize = 1_000;
+
+// The area grows with the amount of objects, so they are always about as dense
+fn boundary(amount: usize) -> AABB {
+    AABB::new(Vec2::splat((amount as f32).sqrt() * 10.0))
+}
+
+// Tile sized boxes spread over the whole boundary
+fn objects(amount: usize) -> Vec<Rect> {
+    let halfsize = boundary(amount).halfsize;
+    let mut rng = StdRng::seed_from_u64(0);
+    (0..amount)
+        .map(|_| {
+            let center = Vec2::new(
+                rng.gen_range(-halfsize.x..halfsize.x),
+                rng.gen_range(-halfsize.y..halfsize.y),
+            );
+            Rect::from_center_half_size(center, Vec2::splat(rng.gen_range(1.0..8.0)))
+        })
+        .collect()
+}
+
+fn build(amount: usize, objects: &[Rect]) -> Quadtree<u32, Rect> {
+    let mut quadtree = Quadtree::new(boundary(amount), Vec2::ZERO, CAPACITY);
+    for (index, object) in objects.iter().enumerate() {
+        quadtree.insert(index as u32, *object);
+    }
+    quadtree
+}
+
+fn insert(c: &mut Criterion) {
+    let mut group = c.benchmark_group("quadtree insert");
+    for amount in SIZES {
+        let objects = objects(amount);
+        group.bench_with_input(
+            BenchmarkId::from_parameter(amount),
+            &objects,
+            |b, objects| {
+                b.iter(|| build(amount, black_box(objects)));
+            },
+        );
+    }
+    group.finish();
+}
+
+fn query(c: &mut Criterion) {
+    let mut group = c.benchmark_group("quadtree query");
+    for amount in SIZES {
+        let objects = objects(amount);
+        let quadtree = build(amount, &objects);
+        // query around some of the objects, like the physics does for every body
+        let ranges: Vec<_> = objects
+            .iter()
+            .take(QUERIES)
+            .map(|object| {
+                (
+                    AABB::new(object.half_size()),
+                    Position::new(object.center()),
+                )
+            })
+            .collect();
+
+        group.bench_with_input(BenchmarkId::new("aabb", amount), &ranges, |b, ranges| {
+            let mut found = Vec::new();
+            b.iter(|| {
+                for (range, position) in ranges {
+                    found.clear();
+                    quadtree.query(range, *position, &mut found);
+                }
+                black_box(&found);
+            });
+        });
+        group.bench_with_input(BenchmarkId::new("radius", amount), &ranges, |b, ranges| {
+            let mut found = Vec::new();
+            b.iter(|| {
+                for (_, position) in ranges {
+                    found.clear();
+                    quadtree.query_radius(position.value, 25.0, &mut found);
+                }
+                black_box(&found);
+            });
+        });
+        group.bench_with_input(BenchmarkId::new("nearest", amount), &ranges, |b, ranges| {
+            let mut found = Vec::new();
+            b.iter(|| {
+                for (_, position) in ranges {
+                    found.clear();
+                    quadtree.nearest(position.value, 8, f32::INFINITY, &mut found);
+                }
+                black_box(&found);
+            });
+        });
+    }
+    group.finish();
+}
+
+fn update(c: &mut Criterion) {
+    let mut group = c.benchmark_group("quadtree update");
+    for amount in SIZES {
+        let objects = objects(amount);
+        // every object moves a bit and back, like bodies do between physics ticks
+        let moved: Vec<_> = objects
+            .iter()
+            .map(|object| Rect::from_center_half_size(object.center() + 0.5, object.half_size()))
+            .collect();
+        let mut quadtree = build(amount, &objects);
+
+        group.bench_function(BenchmarkId::from_parameter(amount), |b| {
+            let mut forth = true;
+            b.iter(|| {
+                let shapes = if forth { &moved } else { &objects };
+                for (index, shape) in shapes.iter().enumerate() {
+                    quadtree.update(index as u32, *shape);
+                }
+                forth = !forth;
+            });
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, insert, query, update);
+criterion_main!(benches);
@@ -3,17 +3,28 @@ use rand::{thread_rng, Rng};
 
 use crate::{
     asset_loader::SpritesLoadingStates,
-    map::{setup_map, MapAabb, TileType},
-    physics::{MovingObject, Position, Velocity, AABB},
+    broadphase::{build_broadphase, Broadphase, BroadphaseKind},
+    map::{setup_map, MapAabb, TileType, TILE_LAYER},
+    physics::{
+        CollisionLayers, ExternalForce, MovingObject, PhysicsTime, Position, Static,
+        StaticBroadphase, Velocity, AABB,
+    },
     player::Player,
-    quadtree::build_quadtree,
+    spatial_query::{SpatialQuery, SpatialQueryFilter},
 };
 
+// the steering factors were tuned for changing the velocity once per frame at 60 fps
+const BOID_STEERING_RATE: f32 = 60.0;
+const BOID_MASS: f32 = 1.0;
+// how often a stuck boid looks for a random position outside of tiles before giving up
+const BOID_TELEPORT_ATTEMPTS: usize = 10;
+
 pub struct BoidPlugin;
 impl Plugin for BoidPlugin {
     fn build(&self, app: &mut App) {
         app.register_type::<BoidParameters>()
             .init_resource::<BoidParameters>()
+            .init_resource::<BoidBroadphase>()
             .add_systems(
                 OnEnter(SpritesLoadingStates::Finished),
                 spawn_boids.after(setup_map),
@@ -22,6 +33,15 @@ impl Plugin for BoidPlugin {
     }
 }
 
+/// The broadphase `move_boids` used in the last frame, with every non static object
+#[derive(Resource, Debug)]
+pub struct BoidBroadphase(pub Box<dyn Broadphase>);
+impl Default for BoidBroadphase {
+    fn default() -> Self {
+        Self(BroadphaseKind::default().new_broadphase(&AABB::default()))
+    }
+}
+
 #[derive(Component, Debug, Default, Clone)]
 struct Boid {
     inside_target: bool,
@@ -33,7 +53,9 @@ pub struct BoidParameters {
     max_velocity: f32,
     min_velocity: f32,
     view_distance: f32,
-    view_distance_aabb: AABB,
+    /// Boids only look at this many of the closest movable things in their view distance, so
+    /// dense flocks dont slow everything down
+    max_neighbours: usize,
 
     avoid_factor: f32,
     centering_factor: f32,
@@ -46,6 +68,8 @@ pub struct BoidParameters {
 
     avoid_obstacles_factor: f32,
     avoid_obstacles_offset: f32,
+    obstacle_lookahead_distance: f32,
+    obstacle_lookahead_factor: f32,
 
     attract_target_factor: f32,
     attract_target_offset: f32,
@@ -53,18 +77,17 @@ pub struct BoidParameters {
     edge_avoidance_distance: f32,
     edge_avoidance_strength: f32,
 
-    quadtree_capacity: usize,
+    broadphase: BroadphaseKind,
 
     player_push_factor: f32,
 }
 impl Default for BoidParameters {
     fn default() -> Self {
-        let view_distance = 25.0;
         Self {
             max_velocity: 600.0,
             min_velocity: 40.0,
-            view_distance,
-            view_distance_aabb: AABB::new(Vec2::splat(view_distance)),
+            view_distance: 25.0,
+            max_neighbours: 64,
 
             avoid_factor: 3.0,
             centering_factor: 0.005,
@@ -77,6 +100,8 @@ impl Default for BoidParameters {
 
             avoid_obstacles_factor: 200.0,
             avoid_obstacles_offset: 10.0,
+            obstacle_lookahead_distance: 100.0,
+            obstacle_lookahead_factor: 2000.0,
 
             attract_target_factor: 200.0,
             attract_target_offset: 10.0,
@@ -84,13 +109,14 @@ impl Default for BoidParameters {
             edge_avoidance_distance: 10.0,
             edge_avoidance_strength: 10.0,
 
-            quadtree_capacity: 2,
+            broadphase: BroadphaseKind::default(),
 
             player_push_factor: 0.1,
         }
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 fn move_boids(
     mut query: Query<(
         Option<&AABB>,
@@ -98,36 +124,59 @@ fn move_boids(
         Entity,
         Option<&Boid>,
         Option<&mut TileType>,
+        Has<Static>,
     )>,
+    static_broadphase: Res<StaticBroadphase>,
+    mut forces: Query<&mut ExternalForce, With<Boid>>,
     map_aabb: Res<MapAabb>,
     boid_params: Res<BoidParameters>,
     window: Query<&Window, With<PrimaryWindow>>,
     time: Res<Time>,
+    physics_time: Res<PhysicsTime>,
+    spatial_query: SpatialQuery,
+    mut boid_broadphase: ResMut<BoidBroadphase>,
 ) {
+    // boids are steered by the physics, they wait while it is paused
+    let delta = physics_time.scale_delta(time.delta_seconds());
+    if delta == 0.0 {
+        return;
+    }
+
     let window = window.get_single().expect("No Primary window");
     let window_halfsize = 0.5 * Vec2::new(window.width(), window.height());
 
-    // new
-    let quadtree = build_quadtree(
-        &query,
+    // static tiles are already in the static broadphase of the physics
+    let broadphase = build_broadphase(
+        &boid_params.broadphase,
+        query.iter().filter(|(_, _, _, _, _, is_static)| !is_static),
         &AABB::new(window_halfsize),
-        boid_params.quadtree_capacity,
-        |(aabb, moving_object, entity, _, _)| (aabb, moving_object, entity),
+        |(aabb, moving_object, entity, _, _, _)| (entity, (aabb.cloned(), moving_object.position)),
     );
 
     let mut boids = Vec::new();
 
     // collect all boids and the stuff in their view range
-    for (aabb, moving_object, entity, _, _) in &query {
+    for (aabb, moving_object, entity, _, _, _) in &query {
         // Filter out non-boids
         if aabb.is_some() {
             continue;
         }
 
-        let position = moving_object.position;
+        let position = moving_object.position.value;
+
+        // the boid finds itself as well
+        let mut neighbours = Vec::new();
+        broadphase.nearest(
+            position,
+            boid_params.max_neighbours + 1,
+            boid_params.view_distance,
+            &mut neighbours,
+        );
 
-        let mut other_stuff = Vec::new();
-        quadtree.query(&boid_params.view_distance_aabb, position, &mut other_stuff);
+        let mut other_stuff: Vec<_> = neighbours.into_iter().map(|(entity, _)| entity).collect();
+        static_broadphase
+            .0
+            .query_radius(position, boid_params.view_distance, &mut other_stuff);
 
         boids.push((entity, other_stuff));
     }
@@ -136,6 +185,12 @@ fn move_boids(
 
     let mut to_teleport = Vec::new();
 
+    // only look ahead for tiles
+    let lookahead_filter = SpatialQueryFilter::new(
+        CollisionLayers::new(CollisionLayers::ALL, TILE_LAYER),
+        Vec::new(),
+    );
+
     // iterate over all boids and the boids in their view range
     for (a_entity, others) in boids {
         let mut final_velocity = Vec2::ZERO;
@@ -150,9 +205,13 @@ fn move_boids(
                     return (pos_acc, vel_acc, amount_acc);
                 }
 
-                // get components of both entities
-                let [(_, mut a_moving_object, _, boid, _), (b_aabb, mut b_moving_object, _, _, tile_type)] =
-                    query.get_many_mut([a_entity, *b_entity]).unwrap();
+                // get components of both entities, skipping entries of the static broadphase whose
+                // entity is already gone
+                let Ok([(_, mut a_moving_object, _, boid, _, _), (b_aabb, mut b_moving_object, _, _, tile_type, _)]) =
+                    query.get_many_mut([a_entity, *b_entity])
+                else {
+                    return (pos_acc, vel_acc, amount_acc);
+                };
 
                 // If b_entity should be teleportet, and the current boid already has some boids
                 // arond it
@@ -166,7 +225,7 @@ fn move_boids(
                 let b_position = b_moving_object.position.value;
                 let b_velocity = b_moving_object.velocity.value;
 
-                object_interactions(b_aabb, a_position, b_position, &boid_params, &mut final_velocity, a_velocity, a_moving_object, &time, &mut rng, &map_aabb, tile_type);
+                object_interactions(b_aabb, a_position, b_position, &boid_params, &mut final_velocity, a_velocity, a_moving_object, delta, &mut rng, &map_aabb, &static_broadphase, tile_type);
 
                 // add to the accumulator
                 (pos_acc + b_position, vel_acc + b_velocity, amount_acc + 1.0)
@@ -178,10 +237,21 @@ fn move_boids(
         }
 
         // Get components of a_entity again, might be able to optimize
-        let (_, mut a_moving_object, _, _, _) = query.get_mut(a_entity).unwrap();
+        let (_, a_moving_object, _, _, _, _) = query.get(a_entity).unwrap();
         let a_position = a_moving_object.position.value;
         let a_velocity = a_moving_object.velocity.value;
 
+        // Steer away from tiles the boid is heading towards
+        if let Some(hit) = spatial_query.cast_ray(
+            a_position,
+            a_velocity,
+            boid_params.obstacle_lookahead_distance,
+            &lookahead_filter,
+        ) {
+            final_velocity +=
+                hit.normal * boid_params.obstacle_lookahead_factor / hit.distance.max(1.0);
+        }
+
         // Steer away from edges of the window
         if a_position.x < -window_halfsize.x + boid_params.edge_avoidance_distance {
             final_velocity.x += boid_params.edge_avoidance_strength;
@@ -229,10 +299,16 @@ fn move_boids(
         final_velocity.y +=
             (rng.gen_range(-0.3..0.3)) * boid_params.max_velocity * boid_params.random_factor;
 
-        a_moving_object.velocity.value += final_velocity * boid_params.multiplier;
+        // steer with a force, so boids move the same at any frame rate
+        forces.get_mut(a_entity).unwrap().force =
+            final_velocity * boid_params.multiplier * a_moving_object.mass * BOID_STEERING_RATE;
     }
+
+    // keep it around for debugging
+    boid_broadphase.0 = broadphase;
 }
 
+#[allow(clippy::too_many_arguments)]
 fn object_interactions(
     b_aabb: Option<&AABB>,
     a_position: Vec2,
@@ -241,15 +317,27 @@ fn object_interactions(
     final_velocity: &mut Vec2,
     a_velocity: Vec2,
     mut a_moving_object: Mut<'_, MovingObject>,
-    time: &Res<'_, Time>,
+    delta: f32,
     rng: &mut rand::prelude::ThreadRng,
     map_aabb: &Res<'_, MapAabb>,
+    static_broadphase: &StaticBroadphase,
     tile_type: Option<Mut<'_, TileType>>,
 ) {
     match tile_type {
         Some(tile_type) => {
             match tile_type.into_inner() {
-                TileType::Tile => {
+                TileType::Tile
+                | TileType::OneWay
+                | TileType::Slope(_)
+                | TileType::Elevator
+                | TileType::Shuttle
+                | TileType::Ice
+                | TileType::Bouncy
+                | TileType::Sticky
+                | TileType::Crate
+                | TileType::HangingPlatform
+                // the water surface is a boundary, just like tiles
+                | TileType::Water => {
                     let b_aabb = b_aabb.expect("Tile doesnt have aabb");
                     let closest_point = a_position
                         .clamp(b_position - b_aabb.halfsize, b_position + b_aabb.halfsize);
@@ -265,14 +353,11 @@ fn object_interactions(
                     // If the boid is inside the block, and has a large enoug velocity, push it
                     // backwards
                     } else if a_velocity.length() > 5.0 {
-                        a_moving_object.position.value -= a_velocity * time.delta_seconds();
+                        a_moving_object.position.value -= a_velocity * delta;
                     } else {
                         // Teleport the boid to a random location
-                        a_moving_object.position.value = Vec2::new(
-                            rng.gen_range(-map_aabb.size.halfsize.x..map_aabb.size.halfsize.x),
-                            rng.gen_range(-map_aabb.size.halfsize.y..map_aabb.size.halfsize.y)
-                                / 2.0,
-                        );
+                        a_moving_object.position.value =
+                            random_free_position(rng, map_aabb, static_broadphase);
                         // Set random velocity
                         a_moving_object.velocity.value.x =
                             (rng.gen::<f32>() - 0.5) * 2.0 * boid_params.max_velocity;
@@ -300,6 +385,8 @@ fn object_interactions(
                         hp -= 1.0;
                     }
                 }
+                // boids arent affected by kill zones or gravity
+                TileType::KillZone | TileType::InvertedGravity | TileType::LowGravity => {}
             }
         }
         // If the object isn't a tile
@@ -316,6 +403,31 @@ fn object_interactions(
     }
 }
 
+// A random position in the lower half of the map, that isnt inside a tile if one can be found
+fn random_free_position(
+    rng: &mut rand::prelude::ThreadRng,
+    map_aabb: &MapAabb,
+    static_broadphase: &StaticBroadphase,
+) -> Vec2 {
+    let halfsize = map_aabb.size.halfsize;
+    let mut position = Vec2::ZERO;
+    let mut tiles = Vec::new();
+
+    for _ in 0..BOID_TELEPORT_ATTEMPTS {
+        position = Vec2::new(
+            rng.gen_range(-halfsize.x..halfsize.x),
+            rng.gen_range(-halfsize.y..halfsize.y) / 2.0,
+        );
+
+        tiles.clear();
+        static_broadphase.0.query_point(position, &mut tiles);
+        if tiles.is_empty() {
+            break;
+        }
+    }
+    position
+}
+
 fn spawn_boids(mut commands: Commands, map_aabb: Res<MapAabb>) {
     let mut rng = thread_rng();
 
@@ -331,8 +443,10 @@ fn spawn_boids(mut commands: Commands, map_aabb: Res<MapAabb>) {
                     rng.gen_range(-400.0..400.0),
                     rng.gen_range(-400.0..400.0),
                 )),
+                mass: BOID_MASS,
                 ..default()
             },
+            ExternalForce::new(Vec2::ZERO, true),
             SpriteBundle {
                 sprite: Sprite {
                     custom_size: Some(Vec2::splat(7.0)),
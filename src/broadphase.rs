@@ -0,0 +1,230 @@
+use bevy::prelude::*;
+
+use crate::{
+    physics::{Position, AABB},
+    quadtree::Quadtree,
+    spatial_hash::SpatialHash,
+};
+
+/// Anything objects can be stored and found by, described by the box around it
+pub trait Shape {
+    /// The center and the halfsize of the box around the shape
+    fn bounds(&self) -> (Vec2, Vec2);
+}
+impl Shape for Vec2 {
+    fn bounds(&self) -> (Vec2, Vec2) {
+        (*self, Vec2::ZERO)
+    }
+}
+impl Shape for Rect {
+    fn bounds(&self) -> (Vec2, Vec2) {
+        (self.center(), self.half_size())
+    }
+}
+// what the physics and boids store, points dont have an aabb
+impl Shape for (Option<AABB>, Position) {
+    fn bounds(&self) -> (Vec2, Vec2) {
+        (
+            self.1.value,
+            self.0.as_ref().map_or(Vec2::ZERO, |aabb| aabb.halfsize),
+        )
+    }
+}
+
+/// What a `Broadphase` stores for every entity
+pub type BroadphaseObject = (Entity, (Option<AABB>, Position));
+
+/// Finds the objects that might overlap a range, before checking them exactly
+pub trait Broadphase: std::fmt::Debug + Send + Sync {
+    fn insert(&mut self, entity: Entity, aabb: Option<AABB>, position: Position);
+
+    /// Does nothing if the entity isnt stored
+    fn remove(&mut self, entity: Entity);
+
+    /// Moves an entity that was already inserted, or inserts it if it wasnt
+    fn update(&mut self, entity: Entity, aabb: Option<AABB>, position: Position) {
+        self.remove(entity);
+        self.insert(entity, aabb, position);
+    }
+
+    /// Pushes every object stored where the range touches the broadphase. They dont have to
+    /// overlap the range, and the same object can be pushed more than once
+    fn query_candidates<'a>(
+        &'a self,
+        range: &AABB,
+        position: Position,
+        found: &mut Vec<&'a BroadphaseObject>,
+    );
+
+    /// Pushes every object overlapping or touching the range once, along with its stored aabb
+    /// and position
+    fn query_objects<'a>(
+        &'a self,
+        range: &AABB,
+        position: Position,
+        found: &mut Vec<&'a BroadphaseObject>,
+    ) {
+        let mut candidates = Vec::new();
+        self.query_candidates(range, position, &mut candidates);
+        found.extend(overlapping(candidates, range, position));
+    }
+
+    /// Pushes every entity overlapping or touching the range once
+    fn query(&self, range: &AABB, position: Position, found: &mut Vec<Entity>) {
+        let mut objects = Vec::new();
+        self.query_objects(range, position, &mut objects);
+        found.extend(objects.into_iter().map(|(entity, _)| *entity));
+    }
+
+    /// Pushes every entity containing the point once
+    fn query_point(&self, point: Vec2, found: &mut Vec<Entity>) {
+        self.query(&AABB::default(), Position::new(point), found);
+    }
+
+    /// Pushes every entity that is at most `radius` away from `center` once
+    fn query_radius(&self, center: Vec2, radius: f32, found: &mut Vec<Entity>) {
+        let mut candidates = Vec::new();
+        self.query_candidates(
+            &AABB::new(Vec2::splat(radius)),
+            Position::new(center),
+            &mut candidates,
+        );
+        found.extend(
+            within(candidates, center, radius)
+                .into_iter()
+                .map(|(entity, _)| *entity),
+        );
+    }
+
+    /// Pushes the `k` entities closest to `point` that are at most `max_distance` away from it,
+    /// nearest first, along with their distance
+    fn nearest(&self, point: Vec2, k: usize, max_distance: f32, found: &mut Vec<(Entity, f32)>) {
+        let mut candidates = Vec::new();
+        self.query_candidates(
+            &AABB::new(Vec2::splat(max_distance)),
+            Position::new(point),
+            &mut candidates,
+        );
+
+        let mut nearest = Vec::new();
+        for (entity, shape) in candidates {
+            let distance = distance(point, shape);
+            if distance <= max_distance {
+                push_nearest(&mut nearest, k, *entity, distance);
+            }
+        }
+        found.extend(nearest);
+    }
+
+    /// Calls `f` with the center and halfsize of every part the broadphase is split into
+    fn for_each_node(&self, f: &mut dyn FnMut(Vec2, Vec2));
+}
+
+/// Keeps the objects overlapping or touching the range, each only once. They are sorted by their
+/// payload, so results dont depend on how they were stored
+pub fn overlapping<'a, P: Ord, S: Shape>(
+    mut candidates: Vec<&'a (P, S)>,
+    range: &AABB,
+    position: Position,
+) -> Vec<&'a (P, S)> {
+    candidates.retain(|(_, shape)| touches(range, position, shape));
+    unique(candidates)
+}
+
+/// Keeps the objects that are at most `radius` away from `center`, each only once
+pub fn within<P: Ord, S: Shape>(
+    mut candidates: Vec<&(P, S)>,
+    center: Vec2,
+    radius: f32,
+) -> Vec<&(P, S)> {
+    candidates.retain(|(_, shape)| distance(center, shape) <= radius);
+    unique(candidates)
+}
+
+fn unique<P: Ord, S>(mut objects: Vec<&(P, S)>) -> Vec<&(P, S)> {
+    objects.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+    objects.dedup_by(|a, b| a.0 == b.0);
+    objects
+}
+
+/// Adds the payload to the `k` nearest ones, if it is closer than one of them and not in them yet.
+/// `nearest` is sorted by the distance
+pub fn push_nearest<P: PartialEq>(
+    nearest: &mut Vec<(P, f32)>,
+    k: usize,
+    payload: P,
+    distance: f32,
+) {
+    if nearest.iter().any(|(other, _)| *other == payload) {
+        return;
+    }
+    let index = nearest.partition_point(|(_, other)| *other <= distance);
+    if index < k {
+        nearest.insert(index, (payload, distance));
+        nearest.truncate(k);
+    }
+}
+
+/// How far the point is from the shape, 0 if it is inside
+pub fn distance(point: Vec2, shape: &impl Shape) -> f32 {
+    let (center, halfsize) = shape.bounds();
+    point.distance(point.clamp(center - halfsize, center + halfsize))
+}
+
+/// Like `collides`, but also true if they only touch, and the shape can be a point.
+/// This way a range without a size, like the one of a ray, still finds the objects it goes through
+pub fn touches(range: &AABB, position: Position, shape: &impl Shape) -> bool {
+    let (center, halfsize) = shape.bounds();
+    let reach = range.halfsize + halfsize;
+    let distance = (position.value - center).abs();
+
+    distance.x <= reach.x && distance.y <= reach.y
+}
+
+/// Which `Broadphase` to build, can be switched at runtime
+#[derive(Debug, Clone, Reflect)]
+pub enum BroadphaseKind {
+    /// Good for sparse scenes, splits the space where there are many objects
+    Quadtree { capacity: usize },
+    /// Good for many evenly spread objects, the cells should be about as big as the queries
+    SpatialHash { cell_size: f32 },
+}
+impl Default for BroadphaseKind {
+    fn default() -> Self {
+        Self::Quadtree { capacity: 2 }
+    }
+}
+impl BroadphaseKind {
+    /// An empty broadphase covering `boundary`, centered on the origin
+    pub fn new_broadphase(&self, boundary: &AABB) -> Box<dyn Broadphase> {
+        match self {
+            // a capacity of 0 would subdivide forever
+            Self::Quadtree { capacity } => Box::new(Quadtree::<Entity, _>::new(
+                boundary.clone(),
+                Vec2::ZERO,
+                (*capacity).max(1),
+            )),
+            Self::SpatialHash { cell_size } => Box::new(SpatialHash::new(*cell_size)),
+        }
+    }
+}
+
+pub fn build_broadphase<T, I, F>(
+    kind: &BroadphaseKind,
+    items: I,
+    aabb: &AABB,
+    transform: F,
+) -> Box<dyn Broadphase>
+where
+    I: IntoIterator<Item = T>,
+    F: Fn(T) -> BroadphaseObject,
+{
+    let mut broadphase = kind.new_broadphase(aabb);
+    items
+        .into_iter()
+        .map(transform)
+        .for_each(|(entity, (aabb, position))| {
+            broadphase.insert(entity, aabb, position);
+        });
+    broadphase
+}
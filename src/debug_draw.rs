@@ -0,0 +1,107 @@
+use bevy::prelude::*;
+
+use crate::{
+    boids::BoidBroadphase,
+    physics::{
+        Contacts, MovingObject, MovingObjectState, PhysicsBroadphase, Sleeping, Static,
+        StaticBroadphase, AABB,
+    },
+};
+
+// how many seconds of movement the velocity lines show
+const VELOCITY_SCALE: f32 = 0.1;
+const CONTACT_POINT_RADIUS: f32 = 2.0;
+
+pub struct DebugDrawPlugin;
+impl Plugin for DebugDrawPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_type::<DebugDraw>()
+            .init_resource::<DebugDraw>()
+            .add_systems(Update, toggle_debug_draw)
+            .add_systems(
+                Update,
+                (draw_broadphases, draw_bodies, draw_contacts)
+                    .after(toggle_debug_draw)
+                    .run_if(|debug_draw: Res<DebugDraw>| debug_draw.enabled),
+            );
+    }
+}
+
+/// Draws the physics and boid internals on top of the game, toggled with f3
+#[derive(Resource, Debug, Default, Reflect)]
+#[reflect(Resource)]
+pub struct DebugDraw {
+    pub enabled: bool,
+}
+
+fn toggle_debug_draw(keyboard_input: Res<ButtonInput<KeyCode>>, mut debug_draw: ResMut<DebugDraw>) {
+    if keyboard_input.just_pressed(KeyCode::F3) {
+        debug_draw.enabled = !debug_draw.enabled;
+    }
+}
+
+// The nodes of the quadtrees, or the cells of the spatial hashes
+fn draw_broadphases(
+    mut gizmos: Gizmos,
+    physics_broadphase: Res<PhysicsBroadphase>,
+    static_broadphase: Res<StaticBroadphase>,
+    boid_broadphase: Res<BoidBroadphase>,
+) {
+    for (broadphase, color) in [
+        (&physics_broadphase.0, Color::ORANGE),
+        (&static_broadphase.0, Color::DARK_GRAY),
+        (&boid_broadphase.0, Color::PURPLE),
+    ] {
+        broadphase.for_each_node(&mut |center, halfsize| {
+            gizmos.rect_2d(center, 0.0, halfsize * 2.0, color);
+        });
+    }
+}
+
+// Every aabb coloured by its contacts, and the velocity of bodies that move
+fn draw_bodies(
+    mut gizmos: Gizmos,
+    query: Query<(&AABB, &MovingObject, Has<Static>, Has<Sleeping>)>,
+) {
+    for (aabb, moving_object, is_static, sleeping) in &query {
+        let position = moving_object.position.value;
+        let color = if is_static {
+            Color::GRAY
+        } else if sleeping {
+            Color::DARK_GREEN
+        } else {
+            state_color(&moving_object.state)
+        };
+        gizmos.rect_2d(position, 0.0, aabb.halfsize * 2.0, color);
+
+        let velocity = moving_object.velocity.value;
+        if velocity != Vec2::ZERO {
+            gizmos.line_2d(position, position + velocity * VELOCITY_SCALE, Color::CYAN);
+        }
+    }
+}
+
+// Touching a wall while standing on the ground gets its own colour, that is what snagging on tile
+// seams looks like
+fn state_color(state: &MovingObjectState) -> Color {
+    let wall = state.left || state.right;
+    match (state.ground, state.ceiling, wall) {
+        (true, _, true) => Color::YELLOW,
+        (true, _, false) => Color::GREEN,
+        (false, true, _) => Color::RED,
+        (false, false, true) => Color::BLUE,
+        (false, false, false) => Color::WHITE,
+    }
+}
+
+// Where bodies touched and how far they were inside each other
+fn draw_contacts(mut gizmos: Gizmos, contacts: Res<Contacts>) {
+    for contact in contacts.iter() {
+        gizmos.circle_2d(contact.point, CONTACT_POINT_RADIUS, Color::FUCHSIA);
+        gizmos.line_2d(
+            contact.point,
+            contact.point + contact.penetration_depth,
+            Color::FUCHSIA,
+        );
+    }
+}
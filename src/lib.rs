@@ -0,0 +1,19 @@
+#![allow(clippy::similar_names)]
+#![allow(clippy::module_name_repetitions)]
+
+pub mod asset_loader;
+pub mod boids;
+pub mod broadphase;
+pub mod camera;
+pub mod debug_draw;
+pub mod fps;
+pub mod map;
+pub mod physics;
+pub mod platform;
+pub mod player;
+pub mod quadtree;
+pub mod rope;
+pub mod spatial_hash;
+pub mod spatial_query;
+#[cfg(target_family = "wasm")]
+pub mod wasm;
@@ -1,30 +1,17 @@
-#![allow(clippy::similar_names)]
-#![allow(clippy::module_name_repetitions)]
 // Conditionally compile the import for development builds only.
 #[cfg(debug_assertions)]
 use bevy_inspector_egui::quick::WorldInspectorPlugin;
+#[cfg(debug_assertions)]
+use bevy_platformer::physics::control_physics_time;
 
-use asset_loader::AssetLoaderPlugin;
 use bevy::{asset::AssetMetaCheck, prelude::*};
-use boids::BoidPlugin;
-use camera::CameraPlugin;
-use fps::FpsPlugin;
-use map::MapPlugin;
-use physics::PhysicsPlugin;
-use player::Playerplugin;
-#[cfg(target_family = "wasm")]
-use wasm::WasmPlugin;
-
-mod asset_loader;
-mod boids;
-mod camera;
-mod fps;
-mod map;
-mod physics;
-mod player;
-mod quadtree;
 #[cfg(target_family = "wasm")]
-mod wasm;
+use bevy_platformer::wasm::WasmPlugin;
+use bevy_platformer::{
+    asset_loader::AssetLoaderPlugin, boids::BoidPlugin, camera::CameraPlugin,
+    debug_draw::DebugDrawPlugin, fps::FpsPlugin, map::MapPlugin, physics::PhysicsPlugin,
+    platform::PlatformPlugin, player::Playerplugin, rope::RopePlugin,
+};
 
 fn main() {
     let mut app = App::new();
@@ -36,7 +23,8 @@ fn main() {
 
     // debug builds
     #[cfg(debug_assertions)]
-    app.add_plugins(WorldInspectorPlugin::default());
+    app.add_plugins(WorldInspectorPlugin::default())
+        .add_systems(Update, control_physics_time);
     app.add_plugins(FpsPlugin);
 
     // wasm stuff
@@ -49,8 +37,11 @@ fn main() {
         Playerplugin,
         MapPlugin,
         AssetLoaderPlugin,
-        PhysicsPlugin,
+        PhysicsPlugin::default(),
+        PlatformPlugin,
+        RopePlugin,
         BoidPlugin,
+        DebugDrawPlugin,
     ));
 
     app.run();
@@ -1,16 +1,30 @@
-use std::fmt::Display;
+use std::{f32::consts::TAU, fmt::Display};
 
 use crate::{
     asset_loader::{Sprites, SpritesLoadingStates},
-    physics::{MovingObject, MovingSpriteSheetBundle, Position, AABB},
+    physics::{
+        CollisionLayers, DistanceJoint, ExternalImpulse, Fluid, Gravity, GravityZone, JointAnchor,
+        MovingObject, MovingSpriteBundle, MovingSpriteSheetBundle, OneWayPlatform, PhysicsMaterial,
+        Position, Sensor, Sleep, Slope, Static, AABB, GRAVITY_CONSTANT,
+    },
+    platform::KinematicPlatform,
+    rope::{spawn_rope, ROPE_LAYER},
+};
+use bevy::{
+    prelude::*,
+    render::{
+        mesh::{Indices, PrimitiveTopology},
+        render_asset::RenderAssetUsages,
+    },
+    sprite::MaterialMesh2dBundle,
 };
-use bevy::prelude::*;
 
 pub struct MapPlugin;
 impl Plugin for MapPlugin {
     fn build(&self, app: &mut App) {
         app.init_resource::<MapAabb>()
             .register_type::<TileType>()
+            .register_type::<KillZone>()
             .add_systems(OnEnter(SpritesLoadingStates::Finished), setup_map);
     }
 }
@@ -27,26 +41,107 @@ impl Default for MapAabb {
     }
 }
 
-#[derive(Component, Debug, Clone, Reflect)]
+#[derive(Component, Debug, Clone, PartialEq, Reflect)]
 #[reflect(Component)]
 pub enum TileType {
     Tile,
     Target(f32),
+    KillZone,
+    OneWay,
+    Slope(Slope),
+    Elevator,
+    Shuttle,
+    Ice,
+    Bouncy,
+    Sticky,
+    InvertedGravity,
+    LowGravity,
+    Water,
+    Crate,
+    HangingPlatform,
+}
+impl TileType {
+    // whether neighbouring pixels of this type get merged into one entity
+    const fn mergeable(&self) -> bool {
+        matches!(
+            self,
+            Self::Tile
+                | Self::KillZone
+                | Self::OneWay
+                | Self::Ice
+                | Self::Bouncy
+                | Self::Sticky
+                | Self::InvertedGravity
+                | Self::LowGravity
+                | Self::Water
+        )
+    }
 }
 impl Display for TileType {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let text = match self {
             Self::Tile => "Tile",
             Self::Target(_) => "Target",
+            Self::KillZone => "KillZone",
+            Self::OneWay => "OneWay",
+            Self::Slope(_) => "Slope",
+            Self::Elevator => "Elevator",
+            Self::Shuttle => "Shuttle",
+            Self::Ice => "Ice",
+            Self::Bouncy => "Bouncy",
+            Self::Sticky => "Sticky",
+            Self::InvertedGravity => "InvertedGravity",
+            Self::LowGravity => "LowGravity",
+            Self::Water => "Water",
+            Self::Crate => "Crate",
+            Self::HangingPlatform => "HangingPlatform",
         };
         write!(f, "{text}")
     }
 }
 
+/// Sends the player back to the spawn when they touch it
+#[derive(Component, Debug, Default, Reflect)]
+#[reflect(Component)]
+pub struct KillZone;
+
 pub const TILE_SIZE: f32 = 64.0;
 pub const TARGET_HP: f32 = 100.0;
+pub const TILE_LAYER: u32 = 1 << 0;
+pub const CRATE_LAYER: u32 = 1 << 2;
+const SLOPE_COLOR: Color = Color::rgb(0.35, 0.3, 0.3);
+// tints for tiles with a physics material
+const ICE_COLOR: Color = Color::rgb(0.7, 0.9, 1.0);
+const BOUNCY_COLOR: Color = Color::rgb(1.0, 0.4, 0.7);
+const STICKY_COLOR: Color = Color::rgb(0.6, 0.45, 0.2);
+const CRATE_COLOR: Color = Color::rgb(0.8, 0.55, 0.3);
+// twice as heavy as the player, so pushing one slows walking down to a third
+const CRATE_MASS: f32 = 2.0;
+// a bit smaller than a tile, so they fit through one tile gaps
+const CRATE_SIZE: f32 = TILE_SIZE * 0.9;
+const CRATE_TERMINAL_VELOCITY: f32 = 1000.0;
+// hanging platforms hang this many tiles below the top of their pixel, from a rope of this many links
+const HANGING_PLATFORM_DROP: f32 = 3.0;
+const HANGING_PLATFORM_LINKS: usize = 8;
+const HANGING_PLATFORM_SIZE: Vec2 = Vec2::new(TILE_SIZE * 2.0, TILE_SIZE / 4.0);
+const HANGING_PLATFORM_MASS: f32 = 3.0;
+// how strong gravity is in low gravity zones, compared to normal gravity
+const LOW_GRAVITY_SCALE: f32 = 0.3;
+// a body as heavy as the player, with the size of one tile, slowly floats up in water
+const WATER_DENSITY: f32 = 1.2 / (TILE_SIZE * TILE_SIZE);
+const WATER_DRAG: f32 = 3.0 / (TILE_SIZE * TILE_SIZE);
+// how many tiles moving platforms travel
+const ELEVATOR_HEIGHT: f32 = 2.0;
+const SHUTTLE_DISTANCE: f32 = 3.0;
+const PLATFORM_SPEED: f32 = 100.0;
 
-pub fn setup_map(mut commands: Commands, sprites: Res<Sprites>, images: Res<Assets<Image>>) {
+pub fn setup_map(
+    mut commands: Commands,
+    sprites: Res<Sprites>,
+    images: Res<Assets<Image>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+) {
     // loading image and getting image size
     let level1_image = images.get(&sprites.level1).unwrap();
     let size = level1_image.size();
@@ -62,45 +157,39 @@ pub fn setup_map(mut commands: Commands, sprites: Res<Sprites>, images: Res<Asse
             let pixel_index = (y * level1_image.size().y + x) as usize * 4; // Assuming 4 bytes per pixel (RGBA)
             let rgba = &level1_image.data[pixel_index..pixel_index + 4];
 
-            match rgba {
-                [255, 255, 255, 255] => {
-                    let mut added = false;
-                    for tile in &mut tiles {
-                        // Vertical:
-                        // if the new tile is in the same horizontal line and one below an existing tile,
-                        // add it to the existing tile
-                        if tile.0.x == x && tile.1.x == x && y == tile.1.y + 1 {
-                            tile.1.y += 1;
-
-                            added = true;
-                            break;
-                        }
-                        // Horizontal:
-                        // if the new tile is in the same vertical line and one to the right of an existing tile,
-                        // add it to the existing tile
-                        if tile.0.y == y && tile.1.y == y && x == tile.1.x + 1 {
-                            tile.1.x += 1;
-
-                            added = true;
-                            break;
-                        }
-                    }
-                    // if the new tile wasnt added to any existing ones, add it to the vec
-                    if !added {
-                        tiles.push((UVec2::new(x, y), UVec2::new(x, y), TileType::Tile));
-                    }
-                }
-                [0, 255, 0, 255] => {
-                    tiles.push((
-                        UVec2::new(x, y),
-                        UVec2::new(x, y),
-                        TileType::Target(TARGET_HP),
-                    ));
-                }
+            let tile_type = match rgba {
+                [255, 255, 255, 255] => TileType::Tile,
+                [0, 255, 0, 255] => TileType::Target(TARGET_HP),
+                [255, 0, 0, 255] => TileType::KillZone,
+                [0, 0, 255, 255] => TileType::OneWay,
+                // slopes, rising or falling from left to right
+                [255, 255, 0, 255] => TileType::Slope(Slope::RISING_45),
+                [255, 0, 255, 255] => TileType::Slope(Slope::FALLING_45),
+                [255, 128, 0, 255] => TileType::Slope(Slope::RISING_22_LOW),
+                [255, 192, 0, 255] => TileType::Slope(Slope::RISING_22_HIGH),
+                [192, 0, 255, 255] => TileType::Slope(Slope::FALLING_22_HIGH),
+                [128, 0, 255, 255] => TileType::Slope(Slope::FALLING_22_LOW),
+                // moving platforms, elevators go up and down, shuttles go right and back
+                [128, 128, 255, 255] => TileType::Elevator,
+                [255, 128, 128, 255] => TileType::Shuttle,
+                // tiles with a different physics material
+                [192, 255, 255, 255] => TileType::Ice,
+                [255, 0, 128, 255] => TileType::Bouncy,
+                [128, 64, 0, 255] => TileType::Sticky,
+                // areas with different gravity
+                [255, 255, 128, 255] => TileType::InvertedGravity,
+                [128, 255, 128, 255] => TileType::LowGravity,
+                [0, 255, 255, 255] => TileType::Water,
+                // pushable crates
+                [192, 128, 64, 255] => TileType::Crate,
+                // the pixel is where the platform hangs from, it should be under a ceiling
+                [128, 128, 128, 255] => TileType::HangingPlatform,
                 other => {
                     dbg!(other);
+                    continue;
                 }
-            }
+            };
+            add_tile(&mut tiles, x, y, tile_type);
         }
     }
 
@@ -123,29 +212,267 @@ pub fn setup_map(mut commands: Commands, sprites: Res<Sprites>, images: Res<Asse
         halfsize += TILE_SIZE / 2.0;
         position *= TILE_SIZE;
 
-        commands.spawn((
-            Name::new(format!("{tile_type}")),
-            MovingSpriteSheetBundle {
-                spritesheet_bundle: SpriteSheetBundle {
-                    atlas: TextureAtlas {
-                        layout: sprites.map_layout.clone(),
-                        index: 0,
+        let moving_object = MovingObject {
+            position: Position::new(position),
+            old_position: Position::new(position),
+            ..default()
+        };
+
+        match tile_type {
+            TileType::KillZone
+            | TileType::InvertedGravity
+            | TileType::LowGravity
+            | TileType::Water => {
+                let kill_zone = tile_type == TileType::KillZone;
+                let (color, gravity_zone, fluid) = match tile_type {
+                    TileType::InvertedGravity => (
+                        Color::rgba(1.0, 1.0, 0.5, 0.2),
+                        Some(GravityZone::new(Vec2::Y * GRAVITY_CONSTANT)),
+                        None,
+                    ),
+                    TileType::LowGravity => (
+                        Color::rgba(0.5, 1.0, 0.5, 0.2),
+                        Some(GravityZone::new(
+                            Vec2::NEG_Y * GRAVITY_CONSTANT * LOW_GRAVITY_SCALE,
+                        )),
+                        None,
+                    ),
+                    TileType::Water => (
+                        Color::rgba(0.0, 0.5, 1.0, 0.4),
+                        None,
+                        Some(Fluid::new(WATER_DENSITY, WATER_DRAG)),
+                    ),
+                    _ => (Color::rgba(1.0, 0.0, 0.0, 0.3), None, None),
+                };
+
+                let mut zone = commands.spawn((
+                    Name::new(format!("{tile_type}")),
+                    MovingSpriteBundle {
+                        sprite_bundle: SpriteBundle {
+                            sprite: Sprite {
+                                color,
+                                custom_size: Some(halfsize * 2.0),
+                                ..default()
+                            },
+                            ..default()
+                        },
+                        aabb: AABB::new(halfsize),
+                        moving_object,
+                        ..default()
+                    },
+                    tile_type,
+                    Sensor::default(),
+                    Static,
+                ));
+                if kill_zone {
+                    zone.insert(KillZone);
+                }
+                if let Some(gravity_zone) = gravity_zone {
+                    zone.insert(gravity_zone);
+                }
+                if let Some(fluid) = fluid {
+                    zone.insert(fluid);
+                }
+            }
+            TileType::Crate => {
+                commands.spawn((
+                    Name::new(format!("{tile_type}")),
+                    MovingSpriteSheetBundle {
+                        spritesheet_bundle: SpriteSheetBundle {
+                            atlas: TextureAtlas {
+                                layout: sprites.map_layout.clone(),
+                                index: 0,
+                            },
+                            texture: sprites.map_texture.clone(),
+                            sprite: Sprite {
+                                color: CRATE_COLOR,
+                                custom_size: Some(Vec2::splat(CRATE_SIZE)),
+                                ..default()
+                            },
+                            ..default()
+                        },
+                        gravity: Gravity::new(
+                            Vec2::NEG_Y * GRAVITY_CONSTANT,
+                            CRATE_TERMINAL_VELOCITY,
+                        ),
+                        aabb: AABB::new(Vec2::splat(CRATE_SIZE / 2.0)),
+                        moving_object: MovingObject {
+                            mass: CRATE_MASS,
+                            ..moving_object
+                        },
+                    },
+                    tile_type,
+                    CollisionLayers::new(CRATE_LAYER, CollisionLayers::ALL),
+                    // levels can have lots of crates, most of them lying around
+                    Sleep::default(),
+                    ExternalImpulse::default(),
+                ));
+            }
+            TileType::HangingPlatform => {
+                let anchor = position + Vec2::Y * TILE_SIZE / 2.0;
+                let platform_position =
+                    anchor - Vec2::Y * (HANGING_PLATFORM_DROP * TILE_SIZE - TILE_SIZE / 2.0);
+                let platform_top = platform_position + Vec2::Y * HANGING_PLATFORM_SIZE.y / 2.0;
+
+                let last_link = spawn_rope(
+                    &mut commands,
+                    JointAnchor::Point(anchor),
+                    anchor,
+                    platform_top,
+                    HANGING_PLATFORM_LINKS,
+                );
+                commands.spawn((
+                    Name::new(format!("{tile_type}")),
+                    MovingSpriteSheetBundle {
+                        spritesheet_bundle: SpriteSheetBundle {
+                            atlas: TextureAtlas {
+                                layout: sprites.map_layout.clone(),
+                                index: 0,
+                            },
+                            texture: sprites.map_texture.clone(),
+                            sprite: Sprite {
+                                custom_size: Some(HANGING_PLATFORM_SIZE),
+                                ..default()
+                            },
+                            ..default()
+                        },
+                        gravity: Gravity::new(
+                            Vec2::NEG_Y * GRAVITY_CONSTANT,
+                            CRATE_TERMINAL_VELOCITY,
+                        ),
+                        aabb: AABB::new(HANGING_PLATFORM_SIZE / 2.0),
+                        moving_object: MovingObject {
+                            mass: HANGING_PLATFORM_MASS,
+                            position: Position::new(platform_position),
+                            old_position: Position::new(platform_position),
+                            ..default()
+                        },
                     },
-                    texture: sprites.map_texture.clone(),
-                    sprite: Sprite {
-                        custom_size: Some(halfsize * 2.0),
+                    tile_type,
+                    DistanceJoint::rope(
+                        JointAnchor::Body(last_link),
+                        HANGING_PLATFORM_SIZE.y / 2.0,
+                    ),
+                    // stands in for a tile, but doesnt get caught on its own rope
+                    CollisionLayers::new(TILE_LAYER, CollisionLayers::ALL & !ROPE_LAYER),
+                ));
+            }
+            TileType::Slope(slope) => {
+                commands.spawn((
+                    Name::new(format!("{tile_type}")),
+                    MaterialMesh2dBundle {
+                        mesh: meshes.add(slope_mesh(halfsize, slope)).into(),
+                        material: materials.add(SLOPE_COLOR),
+                        ..default()
+                    },
+                    AABB::new(halfsize),
+                    moving_object,
+                    tile_type,
+                    slope,
+                    CollisionLayers::new(TILE_LAYER, CollisionLayers::ALL),
+                    Static,
+                ));
+            }
+            _ => {
+                let one_way = tile_type == TileType::OneWay;
+                let platform = match tile_type {
+                    TileType::Elevator => Some(KinematicPlatform::sine(
+                        position,
+                        Vec2::new(0.0, ELEVATOR_HEIGHT * TILE_SIZE),
+                        PLATFORM_SPEED / (ELEVATOR_HEIGHT * TILE_SIZE * TAU),
+                    )),
+                    TileType::Shuttle => Some(KinematicPlatform::waypoints(
+                        vec![position + Vec2::X * SHUTTLE_DISTANCE * TILE_SIZE, position],
+                        PLATFORM_SPEED,
+                    )),
+                    _ => None,
+                };
+                let (material, color) = match tile_type {
+                    TileType::Ice => (Some(PhysicsMaterial::ICE), ICE_COLOR),
+                    TileType::Bouncy => (Some(PhysicsMaterial::BOUNCY), BOUNCY_COLOR),
+                    TileType::Sticky => (Some(PhysicsMaterial::STICKY), STICKY_COLOR),
+                    _ => (None, Color::WHITE),
+                };
+
+                let mut tile = commands.spawn((
+                    Name::new(format!("{tile_type}")),
+                    MovingSpriteSheetBundle {
+                        spritesheet_bundle: SpriteSheetBundle {
+                            atlas: TextureAtlas {
+                                layout: sprites.map_layout.clone(),
+                                index: 0,
+                            },
+                            texture: sprites.map_texture.clone(),
+                            sprite: Sprite {
+                                color,
+                                custom_size: Some(halfsize * 2.0),
+                                ..default()
+                            },
+                            ..default()
+                        },
+                        aabb: AABB::new(halfsize),
+                        moving_object,
                         ..default()
                     },
-                    ..default()
-                },
-                aabb: AABB::new(halfsize),
-                moving_object: MovingObject {
-                    position: Position::new(position),
-                    ..default()
-                },
-                ..default()
-            },
-            tile_type,
-        ));
+                    tile_type,
+                    CollisionLayers::new(TILE_LAYER, CollisionLayers::ALL),
+                ));
+                if one_way {
+                    tile.insert(OneWayPlatform);
+                }
+                // moving platforms are the only tiles that arent static
+                match platform {
+                    Some(platform) => tile.insert(platform),
+                    None => tile.insert(Static),
+                };
+                if let Some(material) = material {
+                    tile.insert(material);
+                }
+            }
+        }
     }
 }
+
+fn add_tile(tiles: &mut Vec<(UVec2, UVec2, TileType)>, x: u32, y: u32, tile_type: TileType) {
+    if tile_type.mergeable() {
+        for tile in tiles.iter_mut() {
+            if tile.2 != tile_type {
+                continue;
+            }
+            // Vertical:
+            // if the new tile is in the same horizontal line and one below an existing tile,
+            // add it to the existing tile
+            if tile.0.x == x && tile.1.x == x && y == tile.1.y + 1 {
+                tile.1.y += 1;
+                return;
+            }
+            // Horizontal:
+            // if the new tile is in the same vertical line and one to the right of an existing tile,
+            // add it to the existing tile
+            if tile.0.y == y && tile.1.y == y && x == tile.1.x + 1 {
+                tile.1.x += 1;
+                return;
+            }
+        }
+    }
+    // if the new tile wasnt added to any existing ones, add it to the vec
+    tiles.push((UVec2::new(x, y), UVec2::new(x, y), tile_type));
+}
+
+// A quad with its top edge following the surface of the slope
+fn slope_mesh(halfsize: Vec2, slope: Slope) -> Mesh {
+    let bottom = -halfsize.y;
+    let positions = vec![
+        [-halfsize.x, bottom, 0.0],
+        [halfsize.x, bottom, 0.0],
+        [halfsize.x, bottom + slope.right * halfsize.y * 2.0, 0.0],
+        [-halfsize.x, bottom + slope.left * halfsize.y * 2.0, 0.0],
+    ];
+
+    Mesh::new(
+        PrimitiveTopology::TriangleList,
+        RenderAssetUsages::default(),
+    )
+    .with_inserted_attribute(Mesh::ATTRIBUTE_POSITION, positions)
+    .with_inserted_indices(Indices::U32(vec![0, 1, 2, 0, 2, 3]))
+}
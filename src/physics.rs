@@ -1,24 +1,122 @@
-use bevy::prelude::*;
+use std::{cell::RefCell, collections::BTreeMap};
 
-use crate::{map::MapAabb, quadtree::build_quadtree};
+use bevy::{
+    ecs::query::QueryData,
+    prelude::*,
+    tasks::ComputeTaskPool,
+    utils::{HashMap, HashSet},
+};
+use thread_local::ThreadLocal;
 
-pub struct PhysicsPlugin;
+use crate::{
+    broadphase::{build_broadphase, Broadphase, BroadphaseKind},
+    map::MapAabb,
+};
+
+pub struct PhysicsPlugin {
+    /// How many physics ticks are run per second
+    pub tick_rate: f64,
+}
+impl Default for PhysicsPlugin {
+    fn default() -> Self {
+        Self {
+            tick_rate: PHYSICS_TICK_RATE,
+        }
+    }
+}
 impl Plugin for PhysicsPlugin {
     fn build(&self, app: &mut App) {
         app.register_type::<Velocity>()
             .register_type::<Position>()
             .register_type::<AABB>()
             .register_type::<Gravity>()
+            .register_type::<GravityZone>()
+            .register_type::<Fluid>()
+            .register_type::<Static>()
+            .register_type::<Sleep>()
+            .register_type::<Sleeping>()
+            .register_type::<DistanceJoint>()
+            .register_type::<JointAnchor>()
+            .register_type::<BroadphaseSettings>()
+            .register_type::<PhysicsTime>()
             .register_type::<MovingObjectState>()
             .register_type::<MovingObject>()
+            .register_type::<ContinuousCollision>()
+            .register_type::<CollisionLayers>()
+            .register_type::<Sensor>()
+            .register_type::<OneWayPlatform>()
+            .register_type::<DropThrough>()
+            .register_type::<Slope>()
+            .register_type::<PhysicsMaterial>()
+            .register_type::<ExternalForce>()
+            .register_type::<ExternalImpulse>()
+            .init_resource::<Contacts>()
+            .init_resource::<SensorOverlaps>()
+            .init_resource::<BroadphaseSettings>()
+            .init_resource::<PhysicsTime>()
+            .init_resource::<PhysicsBroadphase>()
+            .init_resource::<StaticBroadphase>()
+            .init_resource::<RemovedBodies>()
+            .add_event::<CollisionStarted>()
+            .add_event::<CollisionOngoing>()
+            .add_event::<CollisionEnded>()
+            .add_event::<SensorEntered>()
+            .add_event::<SensorExited>()
+            .insert_resource(Time::<Fixed>::from_hz(self.tick_rate))
+            .add_systems(FixedUpdate, advance_physics_time)
             .add_systems(
-                Update,
-                (update_physics, apply_gravity, collisions, stop_movement),
-            );
+                FixedUpdate,
+                (
+                    update_static_broadphase,
+                    tick_drop_through,
+                    wake_bodies,
+                    apply_gravity,
+                    apply_fluid_forces,
+                    apply_external_forces,
+                    update_physics,
+                    update_physics_broadphase,
+                    continuous_collisions,
+                    collisions,
+                    send_collision_events,
+                    update_sensors,
+                    apply_gravity_zones,
+                    fall_asleep,
+                )
+                    .chain()
+                    .after(advance_physics_time)
+                    .run_if(physics_running),
+            )
+            .add_systems(Update, log_physics_events)
+            .add_systems(Update, interpolate_transforms)
+            .add_systems(Last, collect_removed_bodies);
     }
 }
 
-pub const GRAVITY_CONSTANT: f32 = 9.8;
+pub const PHYSICS_TICK_RATE: f64 = 64.0;
+// in units per second squared, matches the old per-frame value at 60 fps
+pub const GRAVITY_CONSTANT: f32 = 9.8 * 60.0;
+// how far a swept body is moved into what it hit, so `collisions` still registers the contact
+const CONTINUOUS_COLLISION_SKIN: f32 = 0.01;
+// how often a swept body can slide along a surface and hit another one in the same tick
+const CONTINUOUS_COLLISION_ITERATIONS: usize = 3;
+// how often the movement is halved to find where a swept body hits the surface of a slope
+const SLOPE_SWEEP_ITERATIONS: usize = 16;
+// how far a body can be below the top of a one way platform and still land on it
+const ONE_WAY_PLATFORM_TOLERANCE: f32 = 0.5;
+// how far a grounded body gets pulled down onto a slope, on top of how much the slope fell below it
+const SLOPE_SNAP_DISTANCE: f32 = 4.0;
+// bodies hitting something slower than this dont bounce, so they can come to rest
+const RESTITUTION_THRESHOLD: f32 = 50.0;
+// how often the contacts of an island are solved every tick, more passes let stacks settle
+const POSITION_ITERATIONS: usize = 4;
+// bodies slower than this, that stay this close to where they stopped, are resting
+const SLEEP_VELOCITY_THRESHOLD: f32 = 5.0;
+const SLEEP_DISTANCE_THRESHOLD: f32 = 1.0;
+// how many seconds a body has to rest before it falls asleep
+const SLEEP_TIME: f32 = 1.0;
+// how much slower or faster the physics gets with every key press in debug builds
+const PHYSICS_TIME_SCALE_STEP: f32 = 0.5;
+const MIN_PHYSICS_TIME_SCALE: f32 = 1.0 / 16.0;
 
 #[derive(Component, Debug, Clone, Copy, Default, Reflect)]
 #[reflect(Component)]
@@ -71,19 +169,73 @@ impl AABB {
 #[derive(Component, Default, Reflect)]
 #[reflect(Component)]
 pub struct Gravity {
-    pub force: f32,
+    /// In units per second squared, pointing down
+    pub force: Vec2,
     pub terminal_velocity: f32,
+    // set while inside a gravity zone, overrides `force`
+    zone_force: Option<Vec2>,
 }
 
 impl Gravity {
-    pub const fn new(force: f32, terminal_velocity: f32) -> Self {
+    pub const fn new(force: Vec2, terminal_velocity: f32) -> Self {
         Self {
             force,
             terminal_velocity,
+            zone_force: None,
+        }
+    }
+
+    /// The force currently pulling the body, taking gravity zones into account
+    pub fn current(&self) -> Vec2 {
+        self.zone_force.unwrap_or(self.force)
+    }
+
+    /// Away from the current gravity, up if there is none
+    pub fn up(&self) -> Vec2 {
+        (-self.current()).try_normalize().unwrap_or(Vec2::Y)
+    }
+
+    /// Perpendicular to `up`, keeps pointing right on screen when gravity is flipped vertically
+    pub fn right(&self) -> Vec2 {
+        let up = self.up();
+        let right = Vec2::new(up.y, -up.x);
+        if right.x < 0.0 {
+            -right
+        } else {
+            right
         }
     }
 }
 
+/// Overrides the gravity of every body inside the `Sensor` it is on
+#[derive(Component, Debug, Default, Clone, Copy, Reflect)]
+#[reflect(Component)]
+pub struct GravityZone {
+    pub force: Vec2,
+}
+impl GravityZone {
+    pub const fn new(force: Vec2) -> Self {
+        Self { force }
+    }
+}
+
+/// Makes the `Sensor` it is on a volume of fluid, that slows down and lifts up the bodies with
+/// mass inside it
+#[derive(Component, Debug, Default, Clone, Copy, Reflect)]
+#[reflect(Component)]
+pub struct Fluid {
+    /// Mass per square unit, bodies lighter than that float
+    pub density: f32,
+    /// How much force slows a body down, per square unit submerged and unit of speed
+    pub drag: f32,
+}
+impl Fluid {
+    pub const fn new(density: f32, drag: f32) -> Self {
+        Self { density, drag }
+    }
+}
+
+/// What the body touches, relative to the direction of its gravity
 #[derive(Component, Clone, Copy, Default, Reflect, Debug)]
 #[reflect(Component)]
 pub struct MovingObjectState {
@@ -92,6 +244,20 @@ pub struct MovingObjectState {
     pub ground: bool,
     pub ceiling: bool,
 }
+impl MovingObjectState {
+    // Sets the state for a contact that pushed the body along `normal`
+    fn set_contact(&mut self, normal: Vec2, up: Vec2, right: Vec2) {
+        if normal.dot(up) > 0.5 {
+            self.ground = true;
+        } else if normal.dot(up) < -0.5 {
+            self.ceiling = true;
+        } else if normal.dot(right) > 0.0 {
+            self.left = true;
+        } else {
+            self.right = true;
+        }
+    }
+}
 
 #[derive(Component, Clone, Copy, Default, Reflect, Debug)]
 #[reflect(Component)]
@@ -110,6 +276,500 @@ pub struct MovingObject {
     pub old_state: MovingObjectState,
 }
 
+/// Pushes a body with mass every physics tick, the heavier it is the less it accelerates
+#[derive(Component, Clone, Copy, Default, Reflect, Debug)]
+#[reflect(Component)]
+pub struct ExternalForce {
+    /// In mass times units per second squared
+    pub force: Vec2,
+    /// Keep applying the force every tick, instead of clearing it after the next one
+    pub persistent: bool,
+}
+impl ExternalForce {
+    pub const fn new(force: Vec2, persistent: bool) -> Self {
+        Self { force, persistent }
+    }
+
+    pub fn apply(&mut self, force: Vec2) {
+        self.force += force;
+    }
+}
+
+/// Changes the velocity of a body with mass once, in the next physics tick. Impulses applied
+/// before that add up
+#[derive(Component, Clone, Copy, Default, Reflect, Debug)]
+#[reflect(Component)]
+pub struct ExternalImpulse {
+    /// In mass times units per second
+    pub impulse: Vec2,
+}
+impl ExternalImpulse {
+    pub fn apply(&mut self, impulse: Vec2) {
+        self.impulse += impulse;
+    }
+}
+
+/// Marks a body that never moves, like map tiles. It is kept in the `StaticBroadphase` instead of
+/// being added to a new broadphase every tick. Moving it requires inserting the marker again
+#[derive(Component, Clone, Copy, Default, Reflect, Debug)]
+#[reflect(Component)]
+pub struct Static;
+
+/// Lets a body fall asleep after resting on the ground for a while. Sleeping bodies arent moved
+/// and dont look for collisions, until they are hit, get an impulse or their ground moves
+#[derive(Component, Clone, Default, Reflect, Debug)]
+#[reflect(Component)]
+pub struct Sleep {
+    // how long the body has been resting
+    idle_time: f32,
+    // where it stopped
+    rest_position: Vec2,
+    // what it fell asleep on, and where that was
+    grounds: Vec<(Entity, Vec2)>,
+}
+
+/// Marks a body that is asleep, added and removed by the physics
+#[derive(Component, Clone, Copy, Default, Reflect, Debug)]
+#[reflect(Component)]
+pub struct Sleeping;
+
+/// What a `DistanceJoint` holds on to
+#[derive(Debug, Clone, Copy, PartialEq, Reflect)]
+pub enum JointAnchor {
+    /// A fixed point in the world
+    Point(Vec2),
+    /// The center of another body, it gets pulled as well if it has mass
+    Body(Entity),
+}
+
+/// Keeps a body between `min_length` and `max_length` away from its anchor. A body can only hang
+/// from one anchor, chains are built by anchoring every link to the one before it
+#[derive(Component, Debug, Clone, Copy, Reflect)]
+#[reflect(Component)]
+pub struct DistanceJoint {
+    pub anchor: JointAnchor,
+    pub min_length: f32,
+    pub max_length: f32,
+}
+impl DistanceJoint {
+    /// Can get slack, but never longer than `length`
+    pub const fn rope(anchor: JointAnchor, length: f32) -> Self {
+        Self {
+            anchor,
+            min_length: 0.0,
+            max_length: length,
+        }
+    }
+
+    /// Always exactly `length` long
+    pub const fn rod(anchor: JointAnchor, length: f32) -> Self {
+        Self {
+            anchor,
+            min_length: length,
+            max_length: length,
+        }
+    }
+
+    /// Holds the body at `point`
+    pub const fn pin(point: Vec2) -> Self {
+        Self::rod(JointAnchor::Point(point), 0.0)
+    }
+}
+
+/// Opt-in swept collision detection for fast bodies, so they can't tunnel through thin tiles
+#[derive(Component, Clone, Copy, Default, Reflect, Debug)]
+#[reflect(Component)]
+pub struct ContinuousCollision;
+
+/// The layers an entity is part of (memberships) and the layers it collides with (filters).
+/// Two entities only collide if each one is part of a layer the other one collides with
+#[derive(Component, Clone, Copy, Reflect, Debug)]
+#[reflect(Component)]
+pub struct CollisionLayers {
+    pub memberships: u32,
+    pub filters: u32,
+}
+impl Default for CollisionLayers {
+    fn default() -> Self {
+        Self::new(Self::ALL, Self::ALL)
+    }
+}
+impl CollisionLayers {
+    pub const ALL: u32 = u32::MAX;
+
+    pub const fn new(memberships: u32, filters: u32) -> Self {
+        Self {
+            memberships,
+            filters,
+        }
+    }
+
+    pub const fn interacts_with(self, other: Self) -> bool {
+        self.memberships & other.filters != 0 && other.memberships & self.filters != 0
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Contact {
+    pub a: Entity,
+    pub b: Entity,
+    /// The direction `a` was pushed out of `b`
+    pub normal: Vec2,
+    /// Roughly where they touched, the middle of their overlap before it was resolved
+    pub point: Vec2,
+    /// As returned by `penetration_depth`
+    pub penetration_depth: Vec2,
+    /// The materials of both bodies combined
+    pub material: PhysicsMaterial,
+}
+impl Contact {
+    // The same contact, seen from `b`
+    fn flipped(self) -> Self {
+        Self {
+            a: self.b,
+            b: self.a,
+            normal: -self.normal,
+            penetration_depth: -self.penetration_depth,
+            ..self
+        }
+    }
+}
+
+/// Sent the first tick two bodies collide
+#[derive(Event, Debug, Clone, Copy)]
+pub struct CollisionStarted(pub Contact);
+
+/// Sent every following tick the two bodies keep colliding
+#[derive(Event, Debug, Clone, Copy)]
+pub struct CollisionOngoing(pub Contact);
+
+/// Sent the first tick two bodies stopped colliding
+#[derive(Event, Debug, Clone, Copy)]
+pub struct CollisionEnded {
+    pub a: Entity,
+    pub b: Entity,
+}
+
+/// The contacts resolved in the current and the previous physics tick, keyed by entity pair
+#[derive(Resource, Default, Debug)]
+pub struct Contacts {
+    current: HashMap<(Entity, Entity), Contact>,
+    previous: HashMap<(Entity, Entity), Contact>,
+}
+impl Contacts {
+    fn insert(&mut self, contact: Contact) {
+        // the same pair is checked from both sides if both have mass, only keep the first one
+        self.current
+            .entry(pair_key(contact.a, contact.b))
+            .or_insert(contact);
+    }
+
+    /// All contacts of the last physics tick
+    pub fn iter(&self) -> impl Iterator<Item = &Contact> {
+        self.current.values()
+    }
+
+    /// The contacts of the last physics tick involving `entity`, turned so `entity` is always `a`
+    pub fn of(&self, entity: Entity) -> impl Iterator<Item = Contact> + '_ {
+        self.current.values().filter_map(move |contact| {
+            if contact.a == entity {
+                Some(*contact)
+            } else if contact.b == entity {
+                Some(contact.flipped())
+            } else {
+                None
+            }
+        })
+    }
+}
+
+/// How a surface reacts to bodies hitting it. Bodies without one use the default material
+#[derive(Component, Debug, Clone, Copy, PartialEq, Reflect)]
+#[reflect(Component)]
+pub struct PhysicsMaterial {
+    /// How much of the sliding speed is lost on impact, and how much grip controllers get
+    pub friction: f32,
+    /// How much of the impact speed is bounced back, 0 doesnt bounce at all
+    pub restitution: f32,
+}
+impl Default for PhysicsMaterial {
+    fn default() -> Self {
+        Self::new(1.0, 0.0)
+    }
+}
+impl PhysicsMaterial {
+    pub const ICE: Self = Self::new(0.05, 0.0);
+    pub const BOUNCY: Self = Self::new(1.0, 0.8);
+    // more grip than normal ground, slows down anything moving on it
+    pub const STICKY: Self = Self::new(3.0, 0.0);
+
+    pub const fn new(friction: f32, restitution: f32) -> Self {
+        Self {
+            friction,
+            restitution,
+        }
+    }
+
+    // The material used for a contact between two bodies
+    fn combine(self, other: Self) -> Self {
+        Self {
+            friction: (self.friction * other.friction).sqrt(),
+            restitution: self.restitution.max(other.restitution),
+        }
+    }
+}
+
+/// Detects `MovingObject`s with mass overlapping its `AABB`, without pushing them
+#[derive(Component, Default, Reflect, Debug, Clone)]
+#[reflect(Component)]
+pub struct Sensor {
+    occupants: Vec<Entity>,
+}
+impl Sensor {
+    /// The entities that overlapped the sensor in the last physics tick
+    pub fn occupants(&self) -> &[Entity] {
+        &self.occupants
+    }
+}
+
+/// Sent the first tick an entity overlaps a sensor
+#[derive(Event, Debug, Clone, Copy)]
+pub struct SensorEntered {
+    pub sensor: Entity,
+    pub entity: Entity,
+}
+
+/// Sent the first tick an entity stopped overlapping a sensor
+#[derive(Event, Debug, Clone, Copy)]
+pub struct SensorExited {
+    pub sensor: Entity,
+    pub entity: Entity,
+}
+
+/// Only blocks bodies landing on it from above
+#[derive(Component, Debug, Default, Reflect)]
+#[reflect(Component)]
+pub struct OneWayPlatform;
+
+/// Lets a body fall through one way platforms until the timer runs out
+#[derive(Component, Debug, Default, Reflect)]
+#[reflect(Component)]
+pub struct DropThrough {
+    timer: Timer,
+}
+impl DropThrough {
+    pub fn new(seconds: f32) -> Self {
+        Self {
+            timer: Timer::from_seconds(seconds, TimerMode::Once),
+        }
+    }
+}
+
+/// Turns the `AABB` into a slope, bodies stand on a straight surface going from `left` to `right`.
+/// Both are fractions of the height of the `AABB`, measured from its bottom
+#[derive(Component, Debug, Default, Clone, Copy, PartialEq, Reflect)]
+#[reflect(Component)]
+pub struct Slope {
+    pub left: f32,
+    pub right: f32,
+}
+impl Slope {
+    pub const RISING_45: Self = Self::new(0.0, 1.0);
+    pub const FALLING_45: Self = Self::new(1.0, 0.0);
+    // 22.5° slopes are split into a low and a high tile
+    pub const RISING_22_LOW: Self = Self::new(0.0, 0.5);
+    pub const RISING_22_HIGH: Self = Self::new(0.5, 1.0);
+    pub const FALLING_22_HIGH: Self = Self::new(1.0, 0.5);
+    pub const FALLING_22_LOW: Self = Self::new(0.5, 0.0);
+
+    pub const fn new(left: f32, right: f32) -> Self {
+        Self { left, right }
+    }
+
+    // The height of the surface at `x`
+    fn surface_at(&self, aabb: &AABB, position: Vec2, x: f32) -> f32 {
+        let fraction =
+            ((x - (position.x - aabb.halfsize.x)) / (aabb.halfsize.x * 2.0)).clamp(0.0, 1.0);
+        let height = self.left + (self.right - self.left) * fraction;
+
+        position.y - aabb.halfsize.y + height * aabb.halfsize.y * 2.0
+    }
+
+    // The highest point of the surface under the bottom edge of `other`
+    fn surface_under(
+        &self,
+        aabb: &AABB,
+        position: Vec2,
+        other: &AABB,
+        other_position: Vec2,
+    ) -> f32 {
+        // the surface is straight, so the highest point is at one of the edges
+        self.surface_at(aabb, position, other_position.x - other.halfsize.x)
+            .max(self.surface_at(aabb, position, other_position.x + other.halfsize.x))
+    }
+
+    // How much the surface rises or falls per unit moved horizontally
+    fn gradient(&self, aabb: &AABB) -> f32 {
+        (self.right - self.left).abs() * aabb.halfsize.y / aabb.halfsize.x
+    }
+}
+
+// How a body overlapping the `AABB` of a slope gets resolved
+enum SlopeContact {
+    // above the surface, not touching it
+    None,
+    // needs to be moved up by this much to stand on the surface
+    Surface(f32),
+    // hit the slope from the side or below, resolve it like a box
+    Box,
+}
+
+/// How an entity takes part in collisions, besides its `AABB` and `MovingObject`
+#[derive(QueryData)]
+pub struct Collider {
+    layers: Option<&'static CollisionLayers>,
+    sensor: Has<Sensor>,
+    one_way_platform: Has<OneWayPlatform>,
+    drop_through: Has<DropThrough>,
+    slope: Option<&'static Slope>,
+    material: Option<&'static PhysicsMaterial>,
+    gravity: Option<&'static Gravity>,
+    is_static: Has<Static>,
+    sleeping: Has<Sleeping>,
+}
+
+/// Pauses or slows down the physics, for debugging and slow motion. Every tick still moves the
+/// bodies by the same amount of time, slowed down physics just skips some of them
+#[derive(Resource, Debug, Reflect)]
+#[reflect(Resource)]
+pub struct PhysicsTime {
+    pub paused: bool,
+    /// How fast the physics runs compared to the rest of the game, from 0 to 1
+    pub scale: f32,
+    // ticks left to run while paused
+    steps: u32,
+    // how far the physics is into its next tick, in ticks
+    progress: f32,
+    // whether the physics runs in the current fixed update
+    ticking: bool,
+    // seconds simulated by all ticks so far
+    elapsed: f32,
+}
+impl Default for PhysicsTime {
+    fn default() -> Self {
+        Self {
+            paused: false,
+            scale: 1.0,
+            steps: 0,
+            progress: 0.0,
+            ticking: false,
+            elapsed: 0.0,
+        }
+    }
+}
+impl PhysicsTime {
+    /// Runs a single tick while paused
+    pub fn step(&mut self) {
+        self.steps += 1;
+    }
+
+    /// `delta` slowed down like the physics, for things that move bodies outside of the physics
+    /// ticks. 0 while paused
+    pub fn scale_delta(&self, delta: f32) -> f32 {
+        delta * self.current_scale()
+    }
+
+    /// How much time the physics simulated so far. Unlike the elapsed time of `Time<Fixed>`, it
+    /// stands still while paused and slows down with the physics
+    pub fn elapsed_seconds(&self) -> f32 {
+        self.elapsed
+    }
+
+    fn current_scale(&self) -> f32 {
+        if self.paused {
+            0.0
+        } else {
+            self.scale.clamp(0.0, 1.0)
+        }
+    }
+}
+
+/// Pauses the physics with f5, steps it one tick with f6, and slows it down or speeds it back up
+/// with f7 and f8
+pub fn control_physics_time(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    mut physics_time: ResMut<PhysicsTime>,
+) {
+    if keyboard_input.just_pressed(KeyCode::F5) {
+        physics_time.paused = !physics_time.paused;
+    }
+    if keyboard_input.just_pressed(KeyCode::F6) {
+        physics_time.paused = true;
+        physics_time.step();
+    }
+    if keyboard_input.just_pressed(KeyCode::F7) {
+        physics_time.scale =
+            (physics_time.scale * PHYSICS_TIME_SCALE_STEP).max(MIN_PHYSICS_TIME_SCALE);
+    }
+    if keyboard_input.just_pressed(KeyCode::F8) {
+        physics_time.scale = (physics_time.scale / PHYSICS_TIME_SCALE_STEP).min(1.0);
+    }
+}
+
+/// Whether the physics runs in the current fixed update, for systems that have to run with it
+pub fn physics_running(physics_time: Res<PhysicsTime>) -> bool {
+    physics_time.ticking
+}
+
+/// (sensor, entity) pairs that overlapped in the current physics tick
+#[derive(Resource, Default, Debug)]
+pub struct SensorOverlaps(Vec<(Entity, Entity)>);
+
+/// Which broadphase the physics uses, changing it rebuilds the `StaticBroadphase`
+#[derive(Resource, Debug, Default, Reflect)]
+#[reflect(Resource)]
+pub struct BroadphaseSettings {
+    pub kind: BroadphaseKind,
+}
+
+/// The broadphase of all non static bodies, updated every physics tick for the bodies that changed.
+/// Positions are from before the collisions were resolved
+#[derive(Resource, Debug)]
+pub struct PhysicsBroadphase(pub Box<dyn Broadphase>);
+impl Default for PhysicsBroadphase {
+    fn default() -> Self {
+        Self(BroadphaseKind::default().new_broadphase(&AABB::default()))
+    }
+}
+
+/// The broadphase of all `Static` bodies, only updated when one of them changes
+#[derive(Resource, Debug)]
+pub struct StaticBroadphase(pub Box<dyn Broadphase>);
+impl Default for StaticBroadphase {
+    fn default() -> Self {
+        Self(BroadphaseKind::default().new_broadphase(&AABB::default()))
+    }
+}
+
+/// The entities that stopped being bodies or static since the last physics tick. Removals are
+/// only kept for two frames, which can pass without a physics tick, so they are collected every
+/// frame until the next tick applies them to the broadphases
+#[derive(Resource, Default, Debug)]
+pub struct RemovedBodies {
+    // lost their `MovingObject` or `AABB`, or were despawned
+    bodies: Vec<Entity>,
+    // lost their `Static` marker, or were despawned
+    statics: Vec<Entity>,
+}
+
+fn pair_key(a: Entity, b: Entity) -> (Entity, Entity) {
+    if a < b {
+        (a, b)
+    } else {
+        (b, a)
+    }
+}
+
 #[derive(Bundle, Default)]
 pub struct MovingObjectBundle {
     transform: Transform,
@@ -134,146 +794,1297 @@ pub struct MovingSpriteSheetBundle {
     pub gravity: Gravity,
 }
 
-fn update_physics(mut query: Query<(&mut MovingObject, &mut Transform)>, time: Res<Time>) {
-    for (mut moving_object, mut transform) in &mut query {
+type PushedBody<'a> = (
+    &'a mut MovingObject,
+    Option<&'a mut ExternalForce>,
+    Option<&'a mut ExternalImpulse>,
+);
+
+// Turns forces and impulses into velocity, bodies without mass cant be pushed
+fn apply_external_forces(mut query: Query<PushedBody, Without<Sleeping>>, time: Res<Time>) {
+    for (mut moving_object, force, impulse) in &mut query {
+        let mass = moving_object.mass;
+
+        if let Some(mut force) = force {
+            if mass != 0.0 {
+                moving_object.velocity.value += force.force / mass * time.delta_seconds();
+            }
+            if !force.persistent {
+                force.force = Vec2::ZERO;
+            }
+        }
+        if let Some(mut impulse) = impulse {
+            if mass != 0.0 {
+                moving_object.velocity.value += impulse.impulse / mass;
+            }
+            impulse.impulse = Vec2::ZERO;
+        }
+    }
+}
+
+pub fn update_physics(mut query: Query<&mut MovingObject, Without<Sleeping>>, time: Res<Time>) {
+    for mut moving_object in &mut query {
         moving_object.old_position = moving_object.position;
         moving_object.old_velocity = moving_object.velocity;
         moving_object.old_state = moving_object.state;
 
         let velocity_value = moving_object.velocity.value;
         moving_object.position.value += velocity_value * time.delta_seconds();
-
-        transform.translation.x = moving_object.position.value.x;
-        transform.translation.y = moving_object.position.value.y;
     }
 }
 
-fn stop_movement(mut query: Query<&mut MovingObject>) {
-    for mut moving_object in &mut query {
-        if (moving_object.state.ceiling || moving_object.state.ground)
-            && (!moving_object.old_state.ceiling || !moving_object.old_state.ground)
-        {
-            moving_object.velocity.value.y = 0.0;
+// Decides if the physics runs in this fixed update. Slowed down physics runs once enough of a
+// tick has added up
+fn advance_physics_time(mut physics_time: ResMut<PhysicsTime>, time: Res<Time>) {
+    if physics_time.paused {
+        physics_time.ticking = physics_time.steps > 0;
+        if physics_time.ticking {
+            physics_time.steps -= 1;
+            // show where the step ended
+            physics_time.progress = 1.0;
         }
-        if (moving_object.state.left || moving_object.state.right)
-            && (!moving_object.old_state.left || !moving_object.old_state.right)
-        {
-            moving_object.velocity.value.x = 0.0;
+    } else {
+        physics_time.steps = 0;
+        // a step while paused leaves it at 1, which would keep it there for good
+        if physics_time.progress >= 1.0 {
+            physics_time.progress = 0.0;
         }
+        physics_time.progress += physics_time.current_scale();
+        physics_time.ticking = physics_time.progress >= 1.0;
+        if physics_time.ticking {
+            physics_time.progress -= 1.0;
+        }
+    }
+
+    // every tick simulates a whole fixed timestep
+    if physics_time.ticking {
+        physics_time.elapsed += time.delta_seconds();
     }
 }
 
-pub fn collisions(mut query: Query<(&AABB, &mut MovingObject, Entity)>, map_aabb: Res<MapAabb>) {
-    // create quadtree
-    let quadtree = build_quadtree(
-        &query,
-        &map_aabb.size,
-        2,
-        |(aabb, moving_object, entity)| (Some(aabb), moving_object, entity),
-    );
+// Runs every frame, places the sprite between the last two physics ticks
+fn interpolate_transforms(
+    mut query: Query<(&MovingObject, &mut Transform), Without<Static>>,
+    fixed_time: Res<Time<Fixed>>,
+    physics_time: Res<PhysicsTime>,
+) {
+    // how far the physics got since its last tick
+    let alpha = (physics_time.progress
+        + fixed_time.overstep_fraction() * physics_time.current_scale())
+    .min(1.0);
+
+    for (moving_object, mut transform) in &mut query {
+        let position = moving_object
+            .old_position
+            .value
+            .lerp(moving_object.position.value, alpha);
+
+        // resting bodies keep their transform, so it isnt marked as changed every frame
+        let translation = position.extend(transform.translation.z);
+        if transform.translation != translation {
+            transform.translation = translation;
+        }
+    }
+}
 
-    // create vec with all collisions to check
+// Runs after `update_physics_broadphase`, so the broadphases already hold the new positions
+pub fn continuous_collisions(
+    mut query: Query<(&AABB, &mut MovingObject, Entity, Has<ContinuousCollision>)>,
+    colliders: Query<Collider>,
+    mut physics_broadphase: ResMut<PhysicsBroadphase>,
+    static_broadphase: Res<StaticBroadphase>,
+) {
     let mut checks = Vec::new();
 
-    // Iterate over all entities that have mass
-    for (aabb, mut moving_object, entity) in &mut query {
-        if moving_object.mass == 0.0 {
+    for (aabb, moving_object, entity, continuous) in &query {
+        if !continuous || moving_object.mass == 0.0 || colliders.get(entity).unwrap().sensor {
             continue;
         }
-        // unset states
-        moving_object.state.left = false;
-        moving_object.state.right = false;
-        moving_object.state.ground = false;
-        moving_object.state.ceiling = false;
+        let movement = moving_object.position.value - moving_object.old_position.value;
+        if movement == Vec2::ZERO {
+            continue;
+        }
+
+        // aabb covering the whole movement of this tick
+        let swept_aabb = AABB::new(aabb.halfsize + movement.abs() / 2.0);
+        let swept_center = Position::new(moving_object.old_position.value + movement / 2.0);
 
-        // create empty vec
         let mut to_check_collision = Vec::new();
-        // add all entities to check against
-        quadtree.query(aabb, moving_object.position, &mut to_check_collision);
+        physics_broadphase
+            .0
+            .query(&swept_aabb, swept_center, &mut to_check_collision);
+        static_broadphase
+            .0
+            .query(&swept_aabb, swept_center, &mut to_check_collision);
 
-        // remove duplicate entries
-        to_check_collision.sort_unstable();
-        to_check_collision.dedup();
+        retain_interacting(&colliders, entity, &mut to_check_collision);
+        // sensors never stop anything
+        to_check_collision.retain(|other| !colliders.get(*other).unwrap().sensor);
 
-        // construct vec with (original_entity, Vec<oter entites>)
         checks.push((entity, to_check_collision));
     }
 
     for (a_entity, others) in checks {
-        for b_entity in others {
-            // skip iteration if a and b are the same entity
-            if a_entity == b_entity {
-                continue;
-            }
+        let (a_aabb, a_moving_object, _, _) = query.get(a_entity).unwrap();
+        let a_collider = colliders.get(a_entity).unwrap();
+        let a_drop_through = a_collider.drop_through;
+        let a_up = a_collider.up();
 
-            // get components of both entities
-            let [(a_aabb, mut a_moving_object, _), (b_aabb, mut b_moving_object, _)] =
-                query.get_many_mut([a_entity, b_entity]).unwrap();
+        let mut position = a_moving_object.old_position.value;
+        let mut movement = a_moving_object.position.value - position;
+        let mut skin = Vec2::ZERO;
 
-            // skip iteration if both objects have a mass of 0 (are stationary)
-            if a_moving_object.mass == 0.0 && b_moving_object.mass == 0.0 {
-                continue;
-            }
-            correct_collisions(&mut a_moving_object, a_aabb, &mut b_moving_object, b_aabb);
+        for _ in 0..CONTINUOUS_COLLISION_ITERATIONS {
+            // find the earliest hit along the remaining movement
+            let earliest_hit = others
+                .iter()
+                .filter(|b_entity| **b_entity != a_entity)
+                .filter_map(|b_entity| {
+                    let (b_aabb, b_moving_object, _, _) = query.get(*b_entity).ok()?;
+                    let b_collider = colliders.get(*b_entity).unwrap();
+                    let start = Position::new(position);
+                    let end = Position::new(position + movement);
+
+                    // the aabb of a slope isnt solid, only the part under its surface. Bodies
+                    // with sideways or flipped gravity treat it as a box, like in `correct_collisions`
+                    let hit = match b_collider.slope {
+                        Some(slope) if a_up == Vec2::Y => {
+                            swept_slope(a_aabb, start, end, b_aabb, b_moving_object.position, slope)
+                        }
+                        _ => swept_aabb(a_aabb, start, end, b_aabb, b_moving_object.position),
+                    }?;
+
+                    // one way platforms can only be hit from above
+                    if b_collider.one_way_platform
+                        && (a_drop_through || hit.1 != axis_towards(a_up))
+                    {
+                        return None;
+                    }
+                    Some(hit)
+                })
+                .min_by(|a, b| a.0.total_cmp(&b.0));
+
+            let Some((time_of_impact, normal)) = earliest_hit else {
+                position += movement;
+                break;
+            };
+
+            // move up to the impact and keep sliding along the surface
+            position += movement * time_of_impact;
+            let remaining = movement * (1.0 - time_of_impact);
+            movement = remaining - normal * remaining.dot(normal);
+            skin -= normal * CONTINUOUS_COLLISION_SKIN;
         }
+
+        let (a_aabb, mut a_moving_object, _, _) = query.get_mut(a_entity).unwrap();
+        a_moving_object.position.value = position + skin;
+        // the pairs of `collisions` are gathered from the broadphase, it needs the clamped position
+        physics_broadphase
+            .0
+            .update(a_entity, Some(a_aabb.clone()), a_moving_object.position);
     }
 }
 
-fn correct_collisions(
-    a_moving_object: &mut MovingObject,
-    a_aabb: &AABB,
-    b_moving_object: &mut MovingObject,
-    b_aabb: &AABB,
+pub fn collisions(
+    mut query: Query<(&AABB, &mut MovingObject, Entity)>,
+    colliders: Query<Collider>,
+    mut contacts: ResMut<Contacts>,
+    mut sensor_overlaps: ResMut<SensorOverlaps>,
+    physics_broadphase: Res<PhysicsBroadphase>,
+    static_broadphase: Res<StaticBroadphase>,
+    joints: Query<(Entity, &DistanceJoint)>,
 ) {
-    let a_pos = a_moving_object.position;
-    let b_pos = b_moving_object.position;
+    contacts.previous = std::mem::take(&mut contacts.current);
 
-    // if there is a collision
-    if let Some(penetration_depth) = penetration_depth(a_aabb, a_pos, b_aabb, b_pos) {
-        let total_mass = a_moving_object.mass + b_moving_object.mass;
-        let a_ratio = a_moving_object.mass / total_mass;
-        let b_ratio = b_moving_object.mass / total_mass;
-
-        // determine which axis to adjust
-        if penetration_depth.x.abs() < penetration_depth.y.abs() {
-            // adjusting position
-            a_moving_object.position.value.x += penetration_depth.x * a_ratio;
-            b_moving_object.position.value.x -= penetration_depth.x * b_ratio;
-
-            // setting horizontal states
-            if penetration_depth.x >= 0.0 {
-                a_moving_object.state.left = true;
-                b_moving_object.state.right = true;
-            } else {
-                a_moving_object.state.right = true;
-                b_moving_object.state.left = true;
-            }
-        } else {
-            // adjusting position
-            a_moving_object.position.value.y += penetration_depth.y * a_ratio;
-            b_moving_object.position.value.y -= penetration_depth.y * b_ratio;
+    // sleeping bodies dont check for collisions, so keep the contacts nobody will check again
+    let checks = |entity| {
+        query
+            .get(entity)
+            .is_ok_and(|(_, moving_object, _)| moving_object.mass != 0.0)
+            && colliders
+                .get(entity)
+                .is_ok_and(|collider| !collider.sensor && !collider.sleeping)
+    };
+    let kept: Vec<Contact> = contacts
+        .previous
+        .values()
+        .filter(|contact| {
+            (colliders
+                .get(contact.a)
+                .is_ok_and(|collider| collider.sleeping)
+                || colliders
+                    .get(contact.b)
+                    .is_ok_and(|collider| collider.sleeping))
+                && !checks(contact.a)
+                && !checks(contact.b)
+        })
+        .copied()
+        .collect();
+    kept.into_iter()
+        .for_each(|contact| contacts.insert(contact));
 
-            if penetration_depth.y >= 0.0 {
-                a_moving_object.state.ground = true;
-                b_moving_object.state.ceiling = true;
-            } else {
-                a_moving_object.state.ceiling = true;
-                b_moving_object.state.ground = true;
-            }
+    // unset the states of the bodies that check for collisions. Sensors only get overlapped, they
+    // dont collide themselves. Sleeping bodies only get hit by others
+    let checks_collisions =
+        |mass: f32, collider: &ColliderItem| mass != 0.0 && !collider.sensor && !collider.sleeping;
+    for (_, mut moving_object, entity) in &mut query {
+        if !checks_collisions(moving_object.mass, &colliders.get(entity).unwrap()) {
+            continue;
         }
+        moving_object.state.left = false;
+        moving_object.state.right = false;
+        moving_object.state.ground = false;
+        moving_object.state.ceiling = false;
     }
-}
 
-fn apply_gravity(mut query: Query<(&mut MovingObject, &Gravity)>) {
+    // gather the (body with mass, other entity) pairs to check in parallel, every thread into its
+    // own buffer. The broadphases are only read
+    let buffers: ThreadLocal<RefCell<(Vec<(Entity, Entity)>, Vec<Entity>)>> = ThreadLocal::new();
+    query.par_iter().for_each(|(aabb, moving_object, entity)| {
+        if !checks_collisions(moving_object.mass, &colliders.get(entity).unwrap()) {
+            return;
+        }
+        let mut buffer = buffers.get_or_default().borrow_mut();
+        let (pairs, to_check_collision) = &mut *buffer;
+
+        // grounded bodies also look where they came from, so they still find the slope they just
+        // walked off
+        let (range, range_position) = if moving_object.old_state.ground {
+            let movement = moving_object.position.value - moving_object.old_position.value;
+            (
+                AABB::new(aabb.halfsize + movement.abs() / 2.0),
+                Position::new(moving_object.old_position.value + movement / 2.0),
+            )
+        } else {
+            (aabb.clone(), moving_object.position)
+        };
+
+        // add all entities to check against
+        to_check_collision.clear();
+        physics_broadphase
+            .0
+            .query(&range, range_position, to_check_collision);
+        static_broadphase
+            .0
+            .query(&range, range_position, to_check_collision);
+
+        // remove entries on layers this entity doesnt collide with, and the entity itself
+        retain_interacting(&colliders, entity, to_check_collision);
+        pairs.extend(
+            to_check_collision
+                .iter()
+                .filter(|other| **other != entity)
+                .map(|other| (entity, *other)),
+        );
+    });
+    let pairs: Vec<(Entity, Entity)> = buffers
+        .into_iter()
+        .flat_map(|buffer| buffer.into_inner().0)
+        .collect();
+
+    // anchors first, so chains are pulled from the top down. A joint is as deep as the chain of
+    // joints it hangs from
+    let mut joints: Vec<(Entity, DistanceJoint)> = joints
+        .iter()
+        .map(|(entity, joint)| (entity, *joint))
+        .collect();
+    let anchors: HashMap<Entity, JointAnchor> = joints
+        .iter()
+        .map(|(entity, joint)| (*entity, joint.anchor))
+        .collect();
+    let depth = |mut entity| {
+        let mut depth = 0;
+        // a joint chain that loops back onto itself would never end
+        while let Some(JointAnchor::Body(anchor)) = anchors.get(&entity) {
+            if depth == anchors.len() {
+                break;
+            }
+            depth += 1;
+            entity = *anchor;
+        }
+        depth
+    };
+    joints.sort_by_cached_key(|(entity, _)| (depth(*entity), *entity));
+
+    let mut islands = islands(pairs, joints, |entity| {
+        query
+            .get(entity)
+            .is_ok_and(|(_, moving_object, _)| moving_object.mass != 0.0)
+            && !colliders.get(entity).unwrap().sensor
+    });
+
+    // solve from the bottom up, so stacks settle onto what is under them first
+    let height = |entity| {
+        query.get(entity).map_or(0.0, |(_, moving_object, _)| {
+            moving_object
+                .position
+                .value
+                .dot(colliders.get(entity).unwrap().up())
+        })
+    };
+    for island in &mut islands {
+        island
+            .pairs
+            .sort_by(|a, b| height(a.0).total_cmp(&height(b.0)).then(a.cmp(b)));
+    }
+
+    // islands only share bodies without mass, which are never moved. So every island is solved
+    // on its own copy of its bodies, at the same time as the others
+    let mut bodies: Vec<HashMap<Entity, (AABB, MovingObject)>> = islands
+        .iter()
+        .map(|island| {
+            island
+                .entities()
+                .filter_map(|entity| {
+                    let (aabb, moving_object, _) = query.get(entity).ok()?;
+                    Some((entity, (aabb.clone(), *moving_object)))
+                })
+                .collect()
+        })
+        .collect();
+    let task_pool = ComputeTaskPool::get();
+    let chunk_size = islands.len().div_ceil(task_pool.thread_num()).max(1);
+    let solved = task_pool.scope(|scope| {
+        for (islands, bodies) in islands
+            .chunks(chunk_size)
+            .zip(bodies.chunks_mut(chunk_size))
+        {
+            let colliders = &colliders;
+            scope.spawn(async move {
+                islands
+                    .iter()
+                    .zip(bodies)
+                    .map(|(island, bodies)| solve_island(island, bodies, colliders))
+                    .collect::<Vec<_>>()
+            });
+        }
+    });
+
+    for (bodies, (island_contacts, overlaps)) in
+        bodies.into_iter().zip(solved.into_iter().flatten())
+    {
+        for (entity, (_, moving_object)) in bodies {
+            if moving_object.mass != 0.0 {
+                *query.get_mut(entity).unwrap().1 = moving_object;
+            }
+        }
+        island_contacts
+            .into_iter()
+            .for_each(|contact| contacts.insert(contact));
+        sensor_overlaps.0.extend(overlaps);
+    }
+}
+
+// Solves the joints and contacts of one island on copies of its bodies. Returns the contacts and
+// the (sensor, entity) overlaps it found
+fn solve_island(
+    island: &Island,
+    bodies: &mut HashMap<Entity, (AABB, MovingObject)>,
+    colliders: &Query<Collider>,
+) -> (Vec<Contact>, Vec<(Entity, Entity)>) {
+    let mut contacts = Vec::new();
+    let mut overlaps = Vec::new();
+
+    // bodies resting on something that cant be pushed, directly or through the bodies under them
+    let mut supported = HashSet::new();
+
+    for pass in 0..POSITION_ITERATIONS {
+        // joints before the contacts, so the contacts get the last word and ropes dont get pulled
+        // through tiles
+        solve_joints(bodies, &island.joints);
+
+        for &(a_entity, b_entity) in &island.pairs {
+            // get both bodies, b might have lost its components since it was inserted
+            let Some([(a_aabb, a_moving_object), (b_aabb, b_moving_object)]) =
+                bodies.get_many_mut([&a_entity, &b_entity])
+            else {
+                continue;
+            };
+
+            // skip iteration if both objects have a mass of 0 (are stationary)
+            if a_moving_object.mass == 0.0 && b_moving_object.mass == 0.0 {
+                continue;
+            }
+
+            let a_collider = colliders.get(a_entity).unwrap();
+            let b_collider = colliders.get(b_entity).unwrap();
+
+            // only detect overlaps with sensors, dont resolve them
+            if b_collider.sensor {
+                if pass == 0
+                    && collides(
+                        a_aabb,
+                        a_moving_object.position,
+                        b_aabb,
+                        b_moving_object.position,
+                    )
+                {
+                    overlaps.push((b_entity, a_entity));
+                }
+                continue;
+            }
+
+            // fall through one way platforms
+            if b_collider.one_way_platform && a_collider.drop_through {
+                continue;
+            }
+
+            // sleeping bodies rest on the ground, so they are as good as the ground
+            let a_supported = supported.contains(&a_entity);
+            let b_supported =
+                b_moving_object.mass == 0.0 || b_collider.sleeping || supported.contains(&b_entity);
+
+            let point = overlap_center(
+                a_aabb,
+                a_moving_object.position,
+                b_aabb,
+                b_moving_object.position,
+            );
+            if let Some((normal, penetration_depth, material)) = correct_collisions(
+                a_moving_object,
+                a_aabb,
+                &a_collider,
+                b_moving_object,
+                b_aabb,
+                &b_collider,
+                (a_supported, b_supported),
+            ) {
+                let up = a_collider.up();
+                if normal.dot(up) > 0.5 && b_supported {
+                    supported.insert(a_entity);
+                } else if normal.dot(up) < -0.5 && a_supported {
+                    supported.insert(b_entity);
+                }
+
+                // later passes only settle the pair, the first contact is the one kept
+                contacts.push(Contact {
+                    a: a_entity,
+                    b: b_entity,
+                    normal,
+                    point,
+                    penetration_depth,
+                    material,
+                });
+            }
+        }
+    }
+
+    (contacts, overlaps)
+}
+
+// Moves every body with a joint, and the body it is anchored to, so the joint has a valid length
+// again. Also takes away the speed that would stretch or squeeze it any further
+fn solve_joints(
+    bodies: &mut HashMap<Entity, (AABB, MovingObject)>,
+    joints: &[(Entity, DistanceJoint)],
+) {
+    for (entity, joint) in joints {
+        let (moving_object, mut anchor) = match joint.anchor {
+            JointAnchor::Point(_) => match bodies.get_mut(entity) {
+                Some((_, moving_object)) => (moving_object, None),
+                None => continue,
+            },
+            JointAnchor::Body(other) => match bodies.get_many_mut([entity, &other]) {
+                Some([(_, moving_object), (_, anchor)]) => (moving_object, Some(anchor)),
+                None => continue,
+            },
+        };
+
+        let anchor_position = match joint.anchor {
+            JointAnchor::Point(point) => point,
+            JointAnchor::Body(_) => anchor.as_ref().unwrap().position.value,
+        };
+        let offset = moving_object.position.value - anchor_position;
+        let length = offset.length();
+        let target = length.clamp(joint.min_length, joint.max_length);
+        if length == target {
+            continue;
+        }
+        // a body right on its anchor gets pushed up
+        let direction = offset.try_normalize().unwrap_or(Vec2::Y);
+
+        let ratios = mass_ratios(
+            moving_object.mass,
+            anchor.as_ref().map_or(0.0, |anchor| anchor.mass),
+        );
+        let correction = direction * (length - target);
+        moving_object.position.value -= correction * ratios.0;
+
+        // only the speed that makes it worse, a rope can still go slack
+        let anchor_velocity = anchor
+            .as_ref()
+            .map_or(Vec2::ZERO, |anchor| anchor.velocity.value);
+        let speed = (moving_object.velocity.value - anchor_velocity).dot(direction);
+        let change = if (length > target) == (speed > 0.0) {
+            direction * speed
+        } else {
+            Vec2::ZERO
+        };
+        moving_object.velocity.value -= change * ratios.0;
+
+        if let Some(anchor) = &mut anchor {
+            anchor.position.value += correction * ratios.1;
+            anchor.velocity.value += change * ratios.1;
+        }
+    }
+}
+
+// Bodies with mass that touch or are joined, directly or through each other. Islands only share
+// bodies without mass, which are never moved, so they can be solved at the same time
+#[derive(Default)]
+struct Island {
+    pairs: Vec<(Entity, Entity)>,
+    joints: Vec<(Entity, DistanceJoint)>,
+}
+impl Island {
+    fn entities(&self) -> impl Iterator<Item = Entity> + '_ {
+        let joined = self.joints.iter().flat_map(|(entity, joint)| {
+            let anchor = match joint.anchor {
+                JointAnchor::Point(_) => None,
+                JointAnchor::Body(anchor) => Some(anchor),
+            };
+            std::iter::once(*entity).chain(anchor)
+        });
+        self.pairs.iter().flat_map(|(a, b)| [*a, *b]).chain(joined)
+    }
+}
+
+// Groups the pairs and joints into islands. Bodies without mass dont connect islands through
+// contacts, since collisions never move them. Joints always connect them, the joint moves both
+// ends. The islands and the pairs in them are sorted, so contacts are solved in the same order no
+// matter how the pairs were gathered. Joints keep their order
+fn islands(
+    mut pairs: Vec<(Entity, Entity)>,
+    joints: Vec<(Entity, DistanceJoint)>,
+    has_mass: impl Fn(Entity) -> bool,
+) -> Vec<Island> {
+    pairs.sort_unstable();
+
+    // union find, every island is rooted at its smallest entity
+    let mut parents = HashMap::new();
+    for (a, b) in &pairs {
+        if has_mass(*b) {
+            join(&mut parents, *a, *b);
+        }
+    }
+    for (entity, joint) in &joints {
+        if let JointAnchor::Body(anchor) = joint.anchor {
+            join(&mut parents, *entity, anchor);
+        }
+    }
+
+    let mut islands = BTreeMap::<Entity, Island>::new();
+    for pair in pairs {
+        islands
+            .entry(find_root(&mut parents, pair.0))
+            .or_default()
+            .pairs
+            .push(pair);
+    }
+    for joint in joints {
+        islands
+            .entry(find_root(&mut parents, joint.0))
+            .or_default()
+            .joints
+            .push(joint);
+    }
+    islands.into_values().collect()
+}
+
+fn join(parents: &mut HashMap<Entity, Entity>, a: Entity, b: Entity) {
+    let a_root = find_root(parents, a);
+    let b_root = find_root(parents, b);
+    if a_root != b_root {
+        parents.insert(a_root.max(b_root), a_root.min(b_root));
+    }
+}
+
+fn find_root(parents: &mut HashMap<Entity, Entity>, entity: Entity) -> Entity {
+    let Some(parent) = parents.get(&entity).copied() else {
+        return entity;
+    };
+    let root = find_root(parents, parent);
+    parents.insert(entity, root);
+    root
+}
+
+impl ColliderItem<'_> {
+    // Entities without `CollisionLayers` are on and collide with every layer
+    fn layers(&self) -> CollisionLayers {
+        self.layers.copied().unwrap_or_default()
+    }
+
+    fn material(&self) -> PhysicsMaterial {
+        self.material.copied().unwrap_or_default()
+    }
+
+    // Bodies without `Gravity` use the default directions
+    fn up(&self) -> Vec2 {
+        self.gravity.map_or(Vec2::Y, Gravity::up)
+    }
+
+    fn right(&self) -> Vec2 {
+        self.gravity.map_or(Vec2::X, Gravity::right)
+    }
+}
+
+// Removes all entities `entity` doesnt collide with. Also removes despawned entities, the
+// persistent broadphases only forget them in the next physics tick
+fn retain_interacting(colliders: &Query<Collider>, entity: Entity, others: &mut Vec<Entity>) {
+    let layers = colliders.get(entity).unwrap().layers();
+    others.retain(|other| {
+        colliders
+            .get(*other)
+            .is_ok_and(|other| layers.interacts_with(other.layers()))
+    });
+}
+
+type ChangedStatic = (With<Static>, Or<(Changed<Static>, Changed<AABB>)>);
+
+// Only updates the static bodies that were added, removed or changed, the whole broadphase is
+// rebuilt when the map or the settings change
+fn update_static_broadphase(
+    query: Query<(&AABB, &MovingObject, Entity), With<Static>>,
+    changed: Query<(&AABB, &MovingObject, Entity), ChangedStatic>,
+    removed: Res<RemovedBodies>,
+    map_aabb: Res<MapAabb>,
+    settings: Res<BroadphaseSettings>,
+    mut static_broadphase: ResMut<StaticBroadphase>,
+) {
+    if map_aabb.is_changed() || settings.is_changed() {
+        static_broadphase.0 = build_broadphase(
+            &settings.kind,
+            &query,
+            &map_aabb.size,
+            |(aabb, moving_object, entity)| (entity, (Some(aabb.clone()), moving_object.position)),
+        );
+        return;
+    }
+
+    // they are cleared by `update_physics_broadphase` later in the tick
+    for entity in &removed.statics {
+        static_broadphase.0.remove(*entity);
+    }
+    for (aabb, moving_object, entity) in &changed {
+        static_broadphase
+            .0
+            .update(entity, Some(aabb.clone()), moving_object.position);
+    }
+}
+
+type ChangedBody = (Without<Static>, Or<(Changed<MovingObject>, Changed<AABB>)>);
+
+// Keeps the broadphase of non static bodies up to date, instead of building it every tick
+pub fn update_physics_broadphase(
+    query: Query<(&AABB, &MovingObject, Entity), Without<Static>>,
+    changed: Query<(&AABB, &MovingObject, Entity), ChangedBody>,
+    added_statics: Query<Entity, Added<Static>>,
+    mut removed: ResMut<RemovedBodies>,
+    map_aabb: Res<MapAabb>,
+    settings: Res<BroadphaseSettings>,
+    mut physics_broadphase: ResMut<PhysicsBroadphase>,
+) {
+    // the removals are only needed once, a rebuilt broadphase doesnt have them anyway
+    let RemovedBodies { bodies, statics } = std::mem::take(&mut *removed);
+
+    if map_aabb.is_changed() || settings.is_changed() {
+        physics_broadphase.0 = build_broadphase(
+            &settings.kind,
+            &query,
+            &map_aabb.size,
+            |(aabb, moving_object, entity)| (entity, (Some(aabb.clone()), moving_object.position)),
+        );
+        return;
+    }
+
+    let broadphase = &mut physics_broadphase.0;
+    // despawned entities or ones that stopped being bodies, static ones have their own broadphase
+    for entity in bodies.into_iter().chain(&added_statics) {
+        broadphase.remove(entity);
+    }
+    for (aabb, moving_object, entity) in &changed {
+        broadphase.update(entity, Some(aabb.clone()), moving_object.position);
+    }
+    // bodies that arent static anymore
+    for entity in statics {
+        if let Ok((aabb, moving_object, entity)) = query.get(entity) {
+            broadphase.update(entity, Some(aabb.clone()), moving_object.position);
+        }
+    }
+}
+
+// Runs every frame, so no removal is missed between physics ticks
+fn collect_removed_bodies(
+    mut removed_objects: RemovedComponents<MovingObject>,
+    mut removed_aabbs: RemovedComponents<AABB>,
+    mut removed_statics: RemovedComponents<Static>,
+    mut removed: ResMut<RemovedBodies>,
+) {
+    let removed = &mut *removed;
+    removed
+        .bodies
+        .extend(removed_objects.read().chain(removed_aabbs.read()));
+    removed.statics.extend(removed_statics.read());
+}
+
+type SleepingBody<'a> = (
+    Entity,
+    &'a MovingObject,
+    &'a mut Sleep,
+    Option<&'a ExternalForce>,
+    Option<&'a ExternalImpulse>,
+);
+
+type MovingBody = (Without<Static>, Without<Sensor>, Without<Sleeping>);
+
+// Wakes sleeping bodies that got an impulse, were moved or hit by a moving body, or whose ground
+// moved or disappeared
+fn wake_bodies(
+    mut commands: Commands,
+    mut query: Query<SleepingBody, With<Sleeping>>,
+    bodies: Query<(&MovingObject, Has<Sleeping>)>,
+    moving_bodies: Query<(&AABB, &MovingObject), MovingBody>,
+    contacts: Res<Contacts>,
+    physics_broadphase: Res<PhysicsBroadphase>,
+) {
+    // bodies without mass dont look for collisions, so nothing would notice them running into a
+    // sleeping body. Wake everything they overlap after they moved
+    let mut run_into = Vec::new();
+    for (aabb, moving_object) in &moving_bodies {
+        if moving_object.mass == 0.0
+            && moving_object.position.value != moving_object.old_position.value
+        {
+            physics_broadphase
+                .0
+                .query(aabb, moving_object.position, &mut run_into);
+        }
+    }
+
+    for (entity, moving_object, mut sleep, force, impulse) in &mut query {
+        let pushed = force.is_some_and(|force| force.force != Vec2::ZERO)
+            || impulse.is_some_and(|impulse| impulse.impulse != Vec2::ZERO);
+        let moved =
+            moving_object.position.value.distance(sleep.rest_position) > SLEEP_DISTANCE_THRESHOLD;
+        let hit = contacts.of(entity).any(|contact| {
+            bodies.get(contact.b).is_ok_and(|(other, sleeping)| {
+                !sleeping
+                    && other.mass != 0.0
+                    && other.velocity.value.length() > SLEEP_VELOCITY_THRESHOLD
+            })
+        });
+        let ground_changed = sleep.grounds.iter().any(|(ground, position)| {
+            bodies
+                .get(*ground)
+                .map_or(true, |(ground, _)| ground.position.value != *position)
+        });
+
+        if pushed || moved || hit || ground_changed || run_into.contains(&entity) {
+            sleep.idle_time = 0.0;
+            sleep.grounds.clear();
+            commands.entity(entity).remove::<Sleeping>();
+        }
+    }
+}
+
+// Puts bodies to sleep that rested on the ground for long enough
+fn fall_asleep(
+    mut commands: Commands,
+    mut query: Query<(Entity, &mut Sleep, Option<&Gravity>), Without<Sleeping>>,
+    mut bodies: Query<&mut MovingObject>,
+    contacts: Res<Contacts>,
+    time: Res<Time>,
+) {
+    let mut asleep = Vec::new();
+    for (entity, mut sleep, gravity) in &mut query {
+        let Ok(moving_object) = bodies.get(entity) else {
+            continue;
+        };
+        let position = moving_object.position.value;
+
+        // start over whenever the body moves
+        if moving_object.velocity.value.length() > SLEEP_VELOCITY_THRESHOLD
+            || position.distance(sleep.rest_position) > SLEEP_DISTANCE_THRESHOLD
+        {
+            sleep.idle_time = 0.0;
+            sleep.rest_position = position;
+            continue;
+        }
+
+        sleep.idle_time += time.delta_seconds();
+        if sleep.idle_time < SLEEP_TIME {
+            continue;
+        }
+
+        // only sleep on something, a body stuck in the air should keep falling once it is free
+        let up = gravity.map_or(Vec2::Y, Gravity::up);
+        let grounds: Vec<(Entity, Vec2)> = contacts
+            .of(entity)
+            .filter(|contact| contact.normal.dot(up) > 0.5)
+            .filter_map(|contact| {
+                let ground = bodies.get(contact.b).ok()?;
+                Some((contact.b, ground.position.value))
+            })
+            .collect();
+        if grounds.is_empty() {
+            continue;
+        }
+
+        sleep.grounds = grounds;
+        sleep.rest_position = position;
+        asleep.push(entity);
+        commands.entity(entity).insert(Sleeping);
+    }
+
+    // stop the body where it is, so the sprite doesnt keep being interpolated
+    for entity in asleep {
+        let mut moving_object = bodies.get_mut(entity).unwrap();
+        moving_object.velocity.value = Vec2::ZERO;
+        moving_object.old_velocity.value = Vec2::ZERO;
+        moving_object.old_position = moving_object.position;
+    }
+}
+
+fn tick_drop_through(
+    mut commands: Commands,
+    mut query: Query<(Entity, &mut DropThrough)>,
+    time: Res<Time>,
+) {
+    for (entity, mut drop_through) in &mut query {
+        if drop_through.timer.tick(time.delta()).finished() {
+            commands.entity(entity).remove::<DropThrough>();
+        }
+    }
+}
+
+fn send_collision_events(
+    contacts: Res<Contacts>,
+    mut started: EventWriter<CollisionStarted>,
+    mut ongoing: EventWriter<CollisionOngoing>,
+    mut ended: EventWriter<CollisionEnded>,
+) {
+    for (pair, contact) in &contacts.current {
+        if contacts.previous.contains_key(pair) {
+            ongoing.send(CollisionOngoing(*contact));
+        } else {
+            started.send(CollisionStarted(*contact));
+        }
+    }
+
+    for (pair, contact) in &contacts.previous {
+        if !contacts.current.contains_key(pair) {
+            ended.send(CollisionEnded {
+                a: contact.a,
+                b: contact.b,
+            });
+        }
+    }
+}
+
+fn update_sensors(
+    mut query: Query<(Entity, &mut Sensor)>,
+    sleeping: Query<(), With<Sleeping>>,
+    mut sensor_overlaps: ResMut<SensorOverlaps>,
+    mut entered: EventWriter<SensorEntered>,
+    mut exited: EventWriter<SensorExited>,
+) {
+    // group the overlapping entities by sensor
+    let mut overlaps: HashMap<Entity, Vec<Entity>> = HashMap::new();
+    for (sensor, entity) in sensor_overlaps.0.drain(..) {
+        overlaps.entry(sensor).or_default().push(entity);
+    }
+
+    for (sensor_entity, mut sensor) in &mut query {
+        let mut occupants = overlaps.remove(&sensor_entity).unwrap_or_default();
+        // sleeping bodies dont look for sensors, but they are still where they were
+        occupants.extend(
+            sensor
+                .occupants
+                .iter()
+                .filter(|entity| sleeping.contains(**entity) && !occupants.contains(entity))
+                .copied()
+                .collect::<Vec<_>>(),
+        );
+
+        for entity in &occupants {
+            if !sensor.occupants.contains(entity) {
+                entered.send(SensorEntered {
+                    sensor: sensor_entity,
+                    entity: *entity,
+                });
+            }
+        }
+        for entity in &sensor.occupants {
+            if !occupants.contains(entity) {
+                exited.send(SensorExited {
+                    sensor: sensor_entity,
+                    entity: *entity,
+                });
+            }
+        }
+
+        // only trigger change detection if the occupants actually changed
+        if sensor.occupants != occupants {
+            sensor.occupants = occupants;
+        }
+    }
+}
+
+fn log_physics_events(
+    mut started: EventReader<CollisionStarted>,
+    mut ongoing: EventReader<CollisionOngoing>,
+    mut ended: EventReader<CollisionEnded>,
+    mut sensor_entered: EventReader<SensorEntered>,
+    mut sensor_exited: EventReader<SensorExited>,
+) {
+    for CollisionStarted(contact) in started.read() {
+        debug!(
+            "collision started: {:?} -> {:?}, normal {}, depth {}",
+            contact.a, contact.b, contact.normal, contact.penetration_depth
+        );
+    }
+    for CollisionOngoing(contact) in ongoing.read() {
+        trace!(
+            "collision ongoing: {:?} -> {:?}, normal {}, depth {}",
+            contact.a,
+            contact.b,
+            contact.normal,
+            contact.penetration_depth
+        );
+    }
+    for CollisionEnded { a, b } in ended.read() {
+        debug!("collision ended: {a:?} -> {b:?}");
+    }
+    for SensorEntered { sensor, entity } in sensor_entered.read() {
+        debug!("{entity:?} entered sensor {sensor:?}");
+    }
+    for SensorExited { sensor, entity } in sensor_exited.read() {
+        debug!("{entity:?} exited sensor {sensor:?}");
+    }
+}
+
+fn correct_collisions(
+    a_moving_object: &mut MovingObject,
+    a_aabb: &AABB,
+    a_collider: &ColliderItem,
+    b_moving_object: &mut MovingObject,
+    b_aabb: &AABB,
+    b_collider: &ColliderItem,
+    supported: (bool, bool),
+) -> Option<(Vec2, Vec2, PhysicsMaterial)> {
+    if !a_collider.layers().interacts_with(b_collider.layers()) {
+        return None;
+    }
+    let material = a_collider.material().combine(b_collider.material());
+
+    let a_pos = a_moving_object.position;
+    let b_pos = b_moving_object.position;
+
+    let penetration_depth = penetration_depth(a_aabb, a_pos, b_aabb, b_pos);
+
+    // slopes push bodies onto their surface, unless they were hit from the side or below.
+    // bodies with sideways or flipped gravity cant stand on them, so they are just boxes for those
+    if let Some(slope) = b_collider.slope {
+        let slope_contact = if a_collider.up() == Vec2::Y {
+            slope_contact(a_moving_object, a_aabb, b_moving_object, b_aabb, slope)
+        } else {
+            SlopeContact::Box
+        };
+        // bodies that walked off the low end of a slope in this tick dont overlap it anymore, they
+        // only get pulled down to where it ended, so they dont hover above the ground below it
+        let walked_off = |offset: f32| {
+            offset <= 0.0
+                && (a_moving_object.old_position.value.x - b_pos.value.x).abs()
+                    < a_aabb.halfsize.x + b_aabb.halfsize.x
+        };
+        match slope_contact {
+            SlopeContact::None => return None,
+            SlopeContact::Surface(offset) if penetration_depth.is_some() || walked_off(offset) => {
+                a_moving_object.position.value.y += offset;
+                a_moving_object.state.ground = true;
+                let ratios = mass_ratios(a_moving_object.mass, b_moving_object.mass);
+                respond_to_contact(
+                    a_moving_object,
+                    b_moving_object,
+                    Vec2::Y,
+                    Vec2::Y,
+                    material,
+                    ratios,
+                );
+                return Some((Vec2::Y, Vec2::new(0.0, offset), material));
+            }
+            SlopeContact::Surface(_) | SlopeContact::Box => {}
+        }
+    }
+
+    // if there is a collision
+    let mut penetration_depth = penetration_depth?;
+
+    let up = a_collider.up();
+
+    // one way platforms only block bodies that were above them and arent moving up, relative to
+    // the gravity of the body
+    let one_way = if b_collider.one_way_platform {
+        let top = axis_towards(up);
+        let extent = |aabb: &AABB| aabb.halfsize.dot(top.abs());
+        let a_old_bottom = a_moving_object.old_position.value.dot(top) - extent(a_aabb);
+        let b_old_top = b_moving_object.old_position.value.dot(top) + extent(b_aabb);
+
+        if a_old_bottom < b_old_top - ONE_WAY_PLATFORM_TOLERANCE
+            || a_moving_object.velocity.value.dot(up) > 0.0
+        {
+            return None;
+        }
+
+        // always push up, even if the body already fell past the platforms center
+        let depth =
+            (b_pos.value.dot(top) + extent(b_aabb)) - (a_pos.value.dot(top) - extent(a_aabb));
+        if top.x != 0.0 {
+            penetration_depth.x = top.x * depth;
+        } else {
+            penetration_depth.y = top.y * depth;
+        }
+        Some((top, top * depth))
+    } else {
+        None
+    };
+
+    // determine which axis to adjust, one way platforms always push up
+    let (normal, correction) = if let Some(one_way) = one_way {
+        one_way
+    } else if penetration_depth.x.abs() < penetration_depth.y.abs() {
+        let normal = if penetration_depth.x >= 0.0 {
+            Vec2::X
+        } else {
+            Vec2::NEG_X
+        };
+        (normal, Vec2::new(penetration_depth.x, 0.0))
+    } else {
+        let normal = if penetration_depth.y >= 0.0 {
+            Vec2::Y
+        } else {
+            Vec2::NEG_Y
+        };
+        (normal, Vec2::new(0.0, penetration_depth.y))
+    };
+
+    // a supported body doesnt get pushed down by what is on top of it, so stacks dont sink
+    let ratios = if supported.1 && normal.dot(up) > 0.5 {
+        (1.0, 0.0)
+    } else if supported.0 && normal.dot(up) < -0.5 {
+        (0.0, 1.0)
+    } else {
+        mass_ratios(a_moving_object.mass, b_moving_object.mass)
+    };
+
+    // adjusting position
+    a_moving_object.position.value += correction * ratios.0;
+    b_moving_object.position.value -= correction * ratios.1;
+
+    // setting states, relative to the gravity of each body. Bodies with mass get pushed along, so
+    // they arent walls
+    let sideways = normal.dot(up).abs() <= 0.5;
+    if !sideways || b_moving_object.mass == 0.0 {
+        a_moving_object
+            .state
+            .set_contact(normal, up, a_collider.right());
+    }
+    if !sideways || a_moving_object.mass == 0.0 {
+        b_moving_object
+            .state
+            .set_contact(-normal, b_collider.up(), b_collider.right());
+    }
+    respond_to_contact(
+        a_moving_object,
+        b_moving_object,
+        normal,
+        up,
+        material,
+        ratios,
+    );
+
+    Some((normal, penetration_depth, material))
+}
+
+// The axis direction closest to `direction`, the side of a box that faces that way
+fn axis_towards(direction: Vec2) -> Vec2 {
+    if direction.x.abs() > direction.y.abs() {
+        Vec2::new(direction.x.signum(), 0.0)
+    } else {
+        Vec2::new(0.0, direction.y.signum())
+    }
+}
+
+// Bounces the bodies off each other and takes away sliding speed, depending on the material.
+// `up` is the up direction of `a`, only the ground and the ceiling have friction
+fn respond_to_contact(
+    a_moving_object: &mut MovingObject,
+    b_moving_object: &mut MovingObject,
+    normal: Vec2,
+    up: Vec2,
+    material: PhysicsMaterial,
+    ratios: (f32, f32),
+) {
+    // bodies without mass are moved by position, riders get carried along instead
+    let b_velocity = if b_moving_object.mass == 0.0 {
+        Vec2::ZERO
+    } else {
+        b_moving_object.velocity.value
+    };
+    let relative_velocity = a_moving_object.velocity.value - b_velocity;
+
+    // only react if they are moving towards each other
+    let normal_speed = relative_velocity.dot(normal);
+    if normal_speed >= 0.0 {
+        return;
+    }
+
+    let restitution = if -normal_speed < RESTITUTION_THRESHOLD {
+        0.0
+    } else {
+        material.restitution
+    };
+    let normal_change = -normal_speed * (1.0 + restitution);
+
+    // friction can slow down the sliding, but never reverse it. Walls dont have any, otherwise
+    // bodies pushed into them would stick instead of falling
+    let friction = if normal.dot(up).abs() > 0.5 {
+        material.friction
+    } else {
+        0.0
+    };
+    let tangent_velocity = relative_velocity - normal * normal_speed;
+    let tangent_change = tangent_velocity.clamp_length_max(normal_change * friction);
+
+    let change = normal * normal_change - tangent_change;
+
+    // split the change like the position correction
+    a_moving_object.velocity.value += change * ratios.0;
+    b_moving_object.velocity.value -= change * ratios.1;
+}
+
+// How much of a correction each body takes, the lighter one moves more. Bodies without mass are
+// never moved
+fn mass_ratios(a_mass: f32, b_mass: f32) -> (f32, f32) {
+    if b_mass == 0.0 {
+        (1.0, 0.0)
+    } else if a_mass == 0.0 {
+        (0.0, 1.0)
+    } else {
+        let total_mass = a_mass + b_mass;
+        (b_mass / total_mass, a_mass / total_mass)
+    }
+}
+
+fn slope_contact(
+    a_moving_object: &MovingObject,
+    a_aabb: &AABB,
+    b_moving_object: &MovingObject,
+    b_aabb: &AABB,
+    slope: &Slope,
+) -> SlopeContact {
+    let a_pos = a_moving_object.position.value;
+    let a_old_pos = a_moving_object.old_position.value;
+    let b_pos = b_moving_object.position.value;
+
+    let surface = slope.surface_under(b_aabb, b_pos, a_aabb, a_pos);
+    let old_surface = slope.surface_under(b_aabb, b_pos, a_aabb, a_old_pos);
+    let bottom = a_pos.y - a_aabb.halfsize.y;
+    let old_bottom = a_old_pos.y - a_aabb.halfsize.y;
+
+    // how far the surface could have moved under the body during this tick
+    let tolerance = (a_pos.x - a_old_pos.x).abs() * slope.gradient(b_aabb) + SLOPE_SNAP_DISTANCE;
+
+    if bottom < surface {
+        if old_bottom >= old_surface - tolerance {
+            SlopeContact::Surface(surface - bottom)
+        } else {
+            SlopeContact::Box
+        }
+    // keep grounded bodies on the surface when walking down, instead of bouncing down in steps
+    } else if a_moving_object.old_state.ground
+        && a_moving_object.velocity.value.y <= 0.0
+        && bottom - surface <= tolerance
+    {
+        SlopeContact::Surface(surface - bottom)
+    } else {
+        SlopeContact::None
+    }
+}
+
+pub fn apply_gravity(
+    mut query: Query<(&mut MovingObject, &Gravity), Without<Sleeping>>,
+    time: Res<Time>,
+) {
     for (mut moving_object, gravity) in &mut query {
-        if moving_object.state.ground {
-            moving_object.velocity.value.y = 0.0;
-        } else if moving_object.velocity.value.y > gravity.terminal_velocity {
-            moving_object.velocity.value.y = gravity.terminal_velocity;
-        } else if moving_object.velocity.value.y < -gravity.terminal_velocity {
-            moving_object.velocity.value.y = -gravity.terminal_velocity;
+        let up = gravity.up();
+        // the velocity along the up direction, the rest is left alone
+        let mut vertical = moving_object.velocity.value.dot(up);
+        let sideways = moving_object.velocity.value - up * vertical;
+
+        // only stop falling, so jumps started between ticks arent eaten
+        if moving_object.state.ground && vertical <= 0.0 {
+            vertical = 0.0;
+        } else if vertical > gravity.terminal_velocity {
+            vertical = gravity.terminal_velocity;
+        } else if vertical < -gravity.terminal_velocity {
+            vertical = -gravity.terminal_velocity;
         } else {
-            moving_object.velocity.value.y -= gravity.force;
+            vertical -= gravity.current().length() * time.delta_seconds();
+        }
+
+        moving_object.velocity.value = sideways + up * vertical;
+    }
+}
+
+type SubmergedBody<'a> = (&'a AABB, &'a mut MovingObject, Option<&'a Gravity>);
+
+// Buoyancy and drag for everything with mass that was inside a fluid in the last tick. They only
+// last for this tick, so they change the velocity directly instead of going through `ExternalForce`
+fn apply_fluid_forces(
+    mut query: Query<SubmergedBody, (Without<Sleeping>, Without<Fluid>)>,
+    fluids: Query<(&AABB, &MovingObject, &Sensor, &Fluid)>,
+    time: Res<Time>,
+) {
+    for (fluid_aabb, fluid_moving_object, sensor, fluid) in &fluids {
+        for entity in sensor.occupants() {
+            let Ok((aabb, mut moving_object, gravity)) = query.get_mut(*entity) else {
+                continue;
+            };
+            if moving_object.mass == 0.0 {
+                continue;
+            }
+
+            // the area of the body that overlaps the fluid
+            let fluid_position = fluid_moving_object.position.value;
+            let position = moving_object.position.value;
+            let overlap = (fluid_position + fluid_aabb.halfsize).min(position + aabb.halfsize)
+                - (fluid_position - fluid_aabb.halfsize).max(position - aabb.halfsize);
+            let submerged_area = overlap.x.max(0.0) * overlap.y.max(0.0);
+
+            // pushes up as hard as gravity pulls on the fluid the body displaces
+            let gravity = gravity.map_or(Vec2::ZERO, Gravity::current);
+            let force = -gravity * fluid.density * submerged_area
+                - moving_object.velocity.value * fluid.drag * submerged_area;
+            let mass = moving_object.mass;
+            moving_object.velocity.value += force / mass * time.delta_seconds();
+        }
+    }
+}
+
+// Bodies inside a gravity zone use its gravity in the next tick
+fn apply_gravity_zones(
+    mut query: Query<(Entity, &mut Gravity)>,
+    zones: Query<(&Sensor, &GravityZone)>,
+) {
+    for (entity, mut gravity) in &mut query {
+        let zone_force = zones
+            .iter()
+            .find(|(sensor, _)| sensor.occupants().contains(&entity))
+            .map(|(_, zone)| zone.force);
+
+        // only trigger change detection if the zone actually changed
+        if gravity.zone_force != zone_force {
+            gravity.zone_force = zone_force;
         }
     }
 }
@@ -304,6 +2115,158 @@ pub fn penetration_depth(
     None
 }
 
+// The center of the area both aabbs cover, or between them if they dont overlap
+fn overlap_center(a_aabb: &AABB, a_pos: Position, b_aabb: &AABB, b_pos: Position) -> Vec2 {
+    let min = (a_pos.value - a_aabb.halfsize).max(b_pos.value - b_aabb.halfsize);
+    let max = (a_pos.value + a_aabb.halfsize).min(b_pos.value + b_aabb.halfsize);
+    (min + max) / 2.0
+}
+
+/// Like `swept_aabb`, but only against the solid part of a slope: the box under its lower edge, the
+/// wall at its higher edge and the surface between them
+pub fn swept_slope(
+    a_aabb: &AABB,
+    a_start: Position,
+    a_end: Position,
+    b_aabb: &AABB,
+    b_pos: Position,
+    slope: &Slope,
+) -> Option<(f32, Vec2)> {
+    let b_pos = b_pos.value;
+    let height = b_aabb.halfsize.y * 2.0;
+    let bottom = b_pos.y - b_aabb.halfsize.y;
+    let low = slope.left.min(slope.right) * height;
+    let high = slope.left.max(slope.right) * height;
+
+    let mut hits = Vec::new();
+    if low > 0.0 {
+        hits.push(swept_aabb(
+            a_aabb,
+            a_start,
+            a_end,
+            &AABB::new(Vec2::new(b_aabb.halfsize.x, low / 2.0)),
+            Position::new(Vec2::new(b_pos.x, bottom + low / 2.0)),
+        ));
+    }
+
+    // only bodies that start completely outside of the higher edge can hit it, the ones walking up
+    // the surface would catch on its top
+    let (edge, outside) = if slope.right > slope.left {
+        let edge = b_pos.x + b_aabb.halfsize.x;
+        (edge, a_start.value.x - a_aabb.halfsize.x >= edge)
+    } else {
+        let edge = b_pos.x - b_aabb.halfsize.x;
+        (edge, a_start.value.x + a_aabb.halfsize.x <= edge)
+    };
+    if high > low && outside {
+        hits.push(swept_aabb(
+            a_aabb,
+            a_start,
+            a_end,
+            &AABB::new(Vec2::new(0.0, high / 2.0)),
+            Position::new(Vec2::new(edge, bottom + high / 2.0)),
+        ));
+    }
+
+    hits.push(swept_slope_surface(
+        a_aabb, a_start, a_end, b_aabb, b_pos, slope,
+    ));
+    hits.into_iter()
+        .flatten()
+        .min_by(|a, b| a.0.total_cmp(&b.0))
+}
+
+// When the bottom of the body goes through the surface of the slope, if it started above it. The
+// surface isnt straight under a box, so the time is found by halving the movement
+fn swept_slope_surface(
+    a_aabb: &AABB,
+    a_start: Position,
+    a_end: Position,
+    b_aabb: &AABB,
+    b_pos: Vec2,
+    slope: &Slope,
+) -> Option<(f32, Vec2)> {
+    let height_above = |time: f32| {
+        let position = a_start.value.lerp(a_end.value, time);
+        position.y - a_aabb.halfsize.y - slope.surface_under(b_aabb, b_pos, a_aabb, position)
+    };
+    // bodies sliding along the surface stay on it
+    if height_above(0.0) < -CONTINUOUS_COLLISION_SKIN
+        || height_above(1.0) >= -CONTINUOUS_COLLISION_SKIN
+    {
+        return None;
+    }
+
+    let (mut above, mut below) = (0.0, 1.0);
+    for _ in 0..SLOPE_SWEEP_ITERATIONS {
+        let time = (above + below) / 2.0;
+        if height_above(time) >= 0.0 {
+            above = time;
+        } else {
+            below = time;
+        }
+    }
+
+    // it has to be over the slope by then
+    let position = a_start.value.lerp(a_end.value, above);
+    if (position.x - b_pos.x).abs() >= a_aabb.halfsize.x + b_aabb.halfsize.x {
+        return None;
+    }
+
+    let rise = (slope.right - slope.left) * b_aabb.halfsize.y / b_aabb.halfsize.x;
+    Some((above, Vec2::new(-rise, 1.0).normalize()))
+}
+
+/// Sweeps `a_aabb` from `a_start` to `a_end` against the stationary `b_aabb`.
+/// Returns the time of impact as a fraction of the movement and the contact normal, pointing
+/// towards `a`
+pub fn swept_aabb(
+    a_aabb: &AABB,
+    a_start: Position,
+    a_end: Position,
+    b_aabb: &AABB,
+    b_pos: Position,
+) -> Option<(f32, Vec2)> {
+    let start = a_start.value;
+    let movement = a_end.value - start;
+    let b_pos = b_pos.value;
+    // sweeping a box against a box is the same as sweeping a point against their sum
+    let halfsize = a_aabb.halfsize + b_aabb.halfsize;
+
+    let mut entry = Vec2::splat(f32::NEG_INFINITY);
+    let mut exit = Vec2::splat(f32::INFINITY);
+
+    for axis in 0..2 {
+        if movement[axis] == 0.0 {
+            // not moving on this axis, so it already has to overlap on it
+            if (start[axis] - b_pos[axis]).abs() >= halfsize[axis] {
+                return None;
+            }
+        } else {
+            let near = b_pos[axis] - halfsize[axis].copysign(movement[axis]);
+            let far = b_pos[axis] + halfsize[axis].copysign(movement[axis]);
+            entry[axis] = (near - start[axis]) / movement[axis];
+            exit[axis] = (far - start[axis]) / movement[axis];
+        }
+    }
+
+    let entry_time = entry.max_element();
+    let exit_time = exit.min_element();
+
+    // no hit if the intervals dont overlap, the hit is behind the start or after the end
+    if entry_time >= exit_time || !(0.0..=1.0).contains(&entry_time) {
+        return None;
+    }
+
+    let normal = if entry.x > entry.y {
+        Vec2::new(-movement.x.signum(), 0.0)
+    } else {
+        Vec2::new(0.0, -movement.y.signum())
+    };
+
+    Some((entry_time, normal))
+}
+
 pub fn collides(a_aabb: &AABB, a_pos: Position, b_aabb: &AABB, b_pos: Position) -> bool {
     let a_pos = a_pos.value;
     let b_pos = b_pos.value;
@@ -314,12 +2277,658 @@ pub fn collides(a_aabb: &AABB, a_pos: Position, b_aabb: &AABB, b_pos: Position)
         && (a_pos.y - a_aabb.halfsize.y) < (b_pos.y + b_aabb.halfsize.y)
 }
 
-pub fn intersects(a_aabb: &AABB, a_pos: Position, b_pos: Position) -> bool {
-    let a_pos = a_pos.value;
-    let b_pos = b_pos.value;
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use bevy::{ecs::system::RunSystemOnce, tasks::TaskPool};
+
+    use super::*;
+    use crate::player::{
+        accelerate_towards, pushed_mass, PLAYER_ACCELERATION, PLAYER_SPEED,
+        PLAYER_TERMINAL_VELOCITY,
+    };
+
+    const DELTA: f32 = 1.0 / PHYSICS_TICK_RATE as f32;
 
-    (a_pos.x - a_aabb.halfsize.x) < b_pos.x
-        && (a_pos.x + a_aabb.halfsize.x) > b_pos.x
-        && (a_pos.y + a_aabb.halfsize.y) > b_pos.y
-        && (a_pos.y - a_aabb.halfsize.y) < b_pos.y
+    fn body(mass: f32, position: Vec2) -> MovingObject {
+        MovingObject {
+            mass,
+            position: Position::new(position),
+            old_position: Position::new(position),
+            ..default()
+        }
+    }
+
+    // A world with what the physics needs to run one tick at a time
+    fn physics_world() -> World {
+        ComputeTaskPool::get_or_init(TaskPool::default);
+
+        let mut world = World::new();
+        world.insert_resource(MapAabb {
+            size: AABB::new(Vec2::splat(1000.0)),
+        });
+        world.init_resource::<BroadphaseSettings>();
+        world.init_resource::<PhysicsBroadphase>();
+        world.init_resource::<StaticBroadphase>();
+        world.init_resource::<RemovedBodies>();
+        world.init_resource::<Contacts>();
+        world.init_resource::<SensorOverlaps>();
+
+        let mut time = Time::<()>::default();
+        time.advance_by(Duration::from_secs_f32(DELTA));
+        world.insert_resource(time);
+        world
+    }
+
+    fn tick(world: &mut World) {
+        world.run_system_once(update_static_broadphase);
+        world.run_system_once(wake_bodies);
+        world.run_system_once(apply_gravity);
+        world.run_system_once(update_physics);
+        world.run_system_once(update_physics_broadphase);
+        world.run_system_once(continuous_collisions);
+        world.run_system_once(collisions);
+        world.run_system_once(fall_asleep);
+    }
+
+    fn position(world: &World, entity: Entity) -> Vec2 {
+        world.get::<MovingObject>(entity).unwrap().position.value
+    }
+
+    #[test]
+    fn stacked_boxes_dont_sink() {
+        let mut world = physics_world();
+        world.spawn((
+            AABB::new(Vec2::new(100.0, 16.0)),
+            body(0.0, Vec2::new(0.0, -16.0)),
+            Static,
+        ));
+        // dropped onto each other, with the heaviest on top
+        let boxes: Vec<Entity> = (0..3)
+            .map(|i| {
+                world
+                    .spawn((
+                        AABB::new(Vec2::splat(16.0)),
+                        body(1.0 + 4.0 * i as f32, Vec2::new(0.0, 24.0 + 40.0 * i as f32)),
+                        Gravity::new(Vec2::NEG_Y * GRAVITY_CONSTANT, 1000.0),
+                    ))
+                    .id()
+            })
+            .collect();
+
+        for _ in 0..PHYSICS_TICK_RATE as usize * 2 {
+            tick(&mut world);
+        }
+
+        for (i, entity) in boxes.into_iter().enumerate() {
+            let height = position(&world, entity).y;
+            assert!((height - (16.0 + 32.0 * i as f32)).abs() < 0.5);
+        }
+    }
+
+    #[test]
+    fn crates_are_pushed_slower_the_heavier_they_are() {
+        let push = |crate_mass: f32| {
+            let mut world = physics_world();
+            world.spawn((
+                AABB::new(Vec2::new(500.0, 16.0)),
+                body(0.0, Vec2::new(0.0, -16.0)),
+                Static,
+            ));
+            let gravity = || Gravity::new(Vec2::NEG_Y * GRAVITY_CONSTANT, 1000.0);
+            let player = world
+                .spawn((
+                    AABB::new(Vec2::splat(16.0)),
+                    body(1.0, Vec2::new(0.0, 16.0)),
+                    gravity(),
+                ))
+                .id();
+            let crate_ = world
+                .spawn((
+                    AABB::new(Vec2::splat(16.0)),
+                    body(crate_mass, Vec2::new(33.0, 16.0)),
+                    gravity(),
+                ))
+                .id();
+
+            // walk right like the player controller does, for a second
+            let mut speeds = Vec::new();
+            for _ in 0..PHYSICS_TICK_RATE as usize {
+                let pushed_mass = pushed_mass(world.resource::<Contacts>(), player, Vec2::X, |b| {
+                    world
+                        .get::<MovingObject>(b)
+                        .map(|moving_object| moving_object.mass)
+                });
+                let mut moving_object = world.get_mut::<MovingObject>(player).unwrap();
+                let speed = PLAYER_SPEED * moving_object.mass / (moving_object.mass + pushed_mass);
+                accelerate_towards(
+                    &mut moving_object,
+                    Vec2::X,
+                    speed,
+                    PLAYER_ACCELERATION * DELTA,
+                );
+                tick(&mut world);
+                speeds.push(world.get::<MovingObject>(crate_).unwrap().velocity.value.x);
+            }
+
+            // the player is still pushing it
+            let gap = position(&world, crate_).x - position(&world, player).x;
+            assert!((gap - 32.0).abs() < 0.01);
+            // the speed it settled at over the last half second
+            let settled = &speeds[speeds.len() / 2..];
+            settled.iter().sum::<f32>() / settled.len() as f32
+        };
+
+        // the speed is shared like momentum, the friction of the floor takes away a bit more
+        for crate_mass in [1.0, 2.0] {
+            let expected = PLAYER_SPEED / (1.0 + crate_mass);
+            let speed = push(crate_mass);
+            assert!(speed <= expected && speed > expected * 0.85);
+        }
+    }
+
+    #[test]
+    fn fluids_lift_bodies_without_touching_their_forces() {
+        let mut world = physics_world();
+        let gravity = || Gravity::new(Vec2::NEG_Y * GRAVITY_CONSTANT, 1000.0);
+        let floating = world
+            .spawn((
+                AABB::new(Vec2::splat(8.0)),
+                body(1.0, Vec2::ZERO),
+                gravity(),
+            ))
+            .id();
+        // wind keeps pushing it to the right
+        let blown = world
+            .spawn((
+                AABB::new(Vec2::splat(8.0)),
+                body(1.0, Vec2::ZERO),
+                gravity(),
+                ExternalForce::new(Vec2::X, true),
+            ))
+            .id();
+        world.spawn((
+            AABB::new(Vec2::splat(100.0)),
+            body(0.0, Vec2::ZERO),
+            Sensor {
+                occupants: vec![floating, blown],
+            },
+            Fluid::new(1.0, 0.0),
+        ));
+
+        for _ in 0..3 {
+            world.run_system_once(apply_fluid_forces);
+            world.run_system_once(apply_external_forces);
+        }
+
+        let velocity = |entity| world.get::<MovingObject>(entity).unwrap().velocity.value;
+        assert!(velocity(floating).y > 0.0);
+        assert_eq!(velocity(floating).y, velocity(blown).y);
+        assert_eq!(world.get::<ExternalForce>(blown).unwrap().force, Vec2::X);
+        assert!((velocity(blown).x - 3.0 * DELTA).abs() < 1e-6);
+    }
+
+    #[test]
+    fn moving_platforms_wake_the_crates_they_run_into() {
+        let mut world = physics_world();
+        world.spawn((
+            AABB::new(Vec2::new(200.0, 16.0)),
+            body(0.0, Vec2::new(0.0, -16.0)),
+            Static,
+        ));
+        let crate_ = world
+            .spawn((
+                AABB::new(Vec2::splat(16.0)),
+                body(1.0, Vec2::new(0.0, 16.0)),
+                Gravity::new(Vec2::NEG_Y * GRAVITY_CONSTANT, 1000.0),
+                Sleep::default(),
+            ))
+            .id();
+        let platform = world
+            .spawn((
+                AABB::new(Vec2::splat(16.0)),
+                body(0.0, Vec2::new(-100.0, 16.0)),
+            ))
+            .id();
+
+        for _ in 0..PHYSICS_TICK_RATE as usize * 2 {
+            tick(&mut world);
+        }
+        assert!(world.get::<Sleeping>(crate_).is_some());
+
+        // shuttle sideways into the crate
+        world
+            .get_mut::<MovingObject>(platform)
+            .unwrap()
+            .velocity
+            .value
+            .x = 300.0;
+        for _ in 0..PHYSICS_TICK_RATE as usize / 2 {
+            tick(&mut world);
+        }
+
+        assert!(world.get::<Sleeping>(crate_).is_none());
+        let gap = position(&world, crate_).x - position(&world, platform).x;
+        assert!(gap >= 32.0 - 1e-3);
+    }
+
+    #[test]
+    fn despawned_bodies_leave_the_broadphases() {
+        let mut world = physics_world();
+        // systems that run more than once, so they only see what changed since their last run
+        let mut physics_tick = Schedule::default();
+        physics_tick.add_systems((update_static_broadphase, update_physics_broadphase).chain());
+        let mut frame_end = Schedule::default();
+        frame_end.add_systems(collect_removed_bodies);
+
+        let moving = world
+            .spawn((AABB::new(Vec2::ONE), body(1.0, Vec2::ZERO)))
+            .id();
+        let tile = world
+            .spawn((AABB::new(Vec2::ONE), body(0.0, Vec2::ZERO), Static))
+            .id();
+        physics_tick.run(&mut world);
+
+        world.despawn(moving);
+        world.despawn(tile);
+        // a few frames without a physics tick
+        for _ in 0..3 {
+            frame_end.run(&mut world);
+            world.clear_trackers();
+        }
+        physics_tick.run(&mut world);
+
+        let mut found = Vec::new();
+        world
+            .resource::<PhysicsBroadphase>()
+            .0
+            .query_point(Vec2::ZERO, &mut found);
+        world
+            .resource::<StaticBroadphase>()
+            .0
+            .query_point(Vec2::ZERO, &mut found);
+        assert!(found.is_empty());
+    }
+
+    #[test]
+    fn stretched_ropes_return_to_their_length() {
+        let mut world = physics_world();
+        let mut anchor = JointAnchor::Point(Vec2::ZERO);
+        let links: Vec<Entity> = (1..=3)
+            .map(|i| {
+                let link = world
+                    .spawn((
+                        AABB::new(Vec2::splat(2.0)),
+                        body(1.0, Vec2::new(0.0, -20.0 * i as f32)),
+                        DistanceJoint::rope(anchor, 10.0),
+                    ))
+                    .id();
+                anchor = JointAnchor::Body(link);
+                link
+            })
+            .collect();
+
+        for _ in 0..PHYSICS_TICK_RATE as usize {
+            tick(&mut world);
+        }
+
+        let mut anchor_position = Vec2::ZERO;
+        for link in links {
+            let link_position = position(&world, link);
+            assert!(link_position.distance(anchor_position) <= 10.0 + 1e-2);
+            anchor_position = link_position;
+        }
+    }
+
+    #[test]
+    fn bodies_pushed_into_walls_keep_falling() {
+        let mut player = body(1.0, Vec2::ZERO);
+        let mut wall = body(0.0, Vec2::X);
+
+        for tick in 1..=10 {
+            // gravity, and the controller walking into the wall
+            player.velocity.value.y -= GRAVITY_CONSTANT * DELTA;
+            player.velocity.value.x = 400.0;
+
+            respond_to_contact(
+                &mut player,
+                &mut wall,
+                Vec2::NEG_X,
+                Vec2::Y,
+                PhysicsMaterial::default(),
+                (1.0, 0.0),
+            );
+
+            assert_eq!(player.velocity.value.x, 0.0);
+            assert!(
+                (player.velocity.value.y + GRAVITY_CONSTANT * DELTA * tick as f32).abs() < 1e-3
+            );
+        }
+    }
+
+    #[test]
+    fn interpolation_resumes_after_stepping() {
+        let mut world = physics_world();
+        world.insert_resource(PhysicsTime {
+            paused: true,
+            scale: 0.5,
+            ..default()
+        });
+        world.resource_mut::<PhysicsTime>().step();
+        world.run_system_once(advance_physics_time);
+        assert!(world.resource::<PhysicsTime>().ticking);
+
+        world.resource_mut::<PhysicsTime>().paused = false;
+        world.run_system_once(advance_physics_time);
+        let physics_time = world.resource::<PhysicsTime>();
+        assert!(!physics_time.ticking);
+        assert_eq!(physics_time.progress, 0.5);
+
+        world.run_system_once(advance_physics_time);
+        let physics_time = world.resource::<PhysicsTime>();
+        assert!(physics_time.ticking);
+        assert_eq!(physics_time.progress, 0.0);
+    }
+
+    #[test]
+    fn interpolation_leaves_resting_transforms_unchanged() {
+        let mut world = physics_world();
+        world.init_resource::<PhysicsTime>();
+        world.init_resource::<Time<Fixed>>();
+
+        let position = Position::new(Vec2::new(10.0, 20.0));
+        let moving_object = MovingObject {
+            position,
+            old_position: position,
+            ..default()
+        };
+        let tile = world
+            .spawn((moving_object, Transform::default(), Static))
+            .id();
+        let sleeping = world
+            .spawn((moving_object, Transform::default(), Sleeping))
+            .id();
+
+        // the first frame places the sleeping body, the static tile is placed by the map
+        world.run_system_once(interpolate_transforms);
+        assert_eq!(
+            world.get::<Transform>(sleeping).unwrap().translation,
+            Vec3::new(10.0, 20.0, 0.0)
+        );
+        assert_eq!(
+            world.get::<Transform>(tile).unwrap().translation,
+            Vec3::ZERO
+        );
+
+        let tick = world.change_tick();
+        world.increment_change_tick();
+        world.run_system_once(interpolate_transforms);
+        for entity in [tile, sleeping] {
+            let transform = world.entity(entity).get_ref::<Transform>().unwrap();
+            assert!(!transform
+                .last_changed()
+                .is_newer_than(tick, world.change_tick()));
+        }
+    }
+
+    fn swept(a_start: Vec2, a_end: Vec2, b_pos: Vec2) -> Option<(f32, Vec2)> {
+        swept_aabb(
+            &AABB::new(Vec2::ONE),
+            Position::new(a_start),
+            Position::new(a_end),
+            &AABB::new(Vec2::ONE),
+            Position::new(b_pos),
+        )
+    }
+
+    #[test]
+    fn swept_aabb_finds_the_time_of_impact_and_normal() {
+        let (time_of_impact, normal) =
+            swept(Vec2::ZERO, Vec2::new(10.0, 0.0), Vec2::new(5.0, 0.0)).unwrap();
+        assert!((time_of_impact - 0.3).abs() < 1e-6);
+        assert_eq!(normal, Vec2::NEG_X);
+
+        // diagonally, the side that is reached last is the one that was hit
+        let (time_of_impact, normal) = swept(
+            Vec2::new(0.0, 10.0),
+            Vec2::new(4.0, -10.0),
+            Vec2::new(2.0, 0.0),
+        )
+        .unwrap();
+        assert!((time_of_impact - 0.4).abs() < 1e-6);
+        assert_eq!(normal, Vec2::Y);
+    }
+
+    #[test]
+    fn swept_aabb_doesnt_hit_what_it_starts_inside_of() {
+        assert!(swept(
+            Vec2::new(4.0, 0.0),
+            Vec2::new(14.0, 0.0),
+            Vec2::new(5.0, 0.0)
+        )
+        .is_none());
+    }
+
+    #[test]
+    fn swept_aabb_doesnt_hit_what_it_moves_away_from() {
+        // touching on the left, moving left
+        assert!(swept(
+            Vec2::new(3.0, 0.0),
+            Vec2::new(-7.0, 0.0),
+            Vec2::new(5.0, 0.0)
+        )
+        .is_none());
+
+        // touching, but moving towards it hits right away
+        let (time_of_impact, normal) = swept(
+            Vec2::new(3.0, 0.0),
+            Vec2::new(13.0, 0.0),
+            Vec2::new(5.0, 0.0),
+        )
+        .unwrap();
+        assert_eq!(time_of_impact, 0.0);
+        assert_eq!(normal, Vec2::NEG_X);
+    }
+
+    #[test]
+    fn swept_aabb_without_movement_on_one_axis() {
+        // falling straight down onto it
+        let (time_of_impact, normal) =
+            swept(Vec2::new(1.0, 10.0), Vec2::new(1.0, -10.0), Vec2::ZERO).unwrap();
+        assert!((time_of_impact - 0.4).abs() < 1e-6);
+        assert_eq!(normal, Vec2::Y);
+
+        // falling past it, or only grazing its side
+        assert!(swept(Vec2::new(3.0, 10.0), Vec2::new(3.0, -10.0), Vec2::ZERO).is_none());
+        assert!(swept(Vec2::new(2.0, 10.0), Vec2::new(2.0, -10.0), Vec2::ZERO).is_none());
+
+        // not moving at all
+        assert!(swept(Vec2::new(0.0, 3.0), Vec2::new(0.0, 3.0), Vec2::ZERO).is_none());
+    }
+
+    #[test]
+    fn fast_bodies_dont_tunnel_through_thin_tiles() {
+        let mut world = physics_world();
+        // two tiles thinner than a tick of falling, one body is swept and one isnt
+        let bodies: Vec<Entity> = [-100.0, 100.0]
+            .into_iter()
+            .map(|x| {
+                world.spawn((
+                    AABB::new(Vec2::new(50.0, 1.0)),
+                    body(0.0, Vec2::new(x, 0.0)),
+                    Static,
+                ));
+                let mut falling = body(1.0, Vec2::new(x, 10.0));
+                falling.velocity.value.y = -PLAYER_TERMINAL_VELOCITY;
+                world
+                    .spawn((
+                        AABB::new(Vec2::splat(4.0)),
+                        falling,
+                        Gravity::new(Vec2::NEG_Y * GRAVITY_CONSTANT, PLAYER_TERMINAL_VELOCITY),
+                    ))
+                    .id()
+            })
+            .collect();
+        world.entity_mut(bodies[0]).insert(ContinuousCollision);
+
+        for _ in 0..10 {
+            tick(&mut world);
+        }
+
+        // resting on top of the tile
+        assert!((position(&world, bodies[0]).y - 5.0).abs() < 0.1);
+        assert!(position(&world, bodies[1]).y < -5.0);
+    }
+
+    // Walks a body with normal gravity over tiles, resolving it against all of them like
+    // `solve_island` does. Tiles without a slope are boxes. Returns where the body is after every
+    // tick
+    fn walk(tiles: &[(Vec2, Option<Slope>)], start: Vec2, speed: f32, ticks: usize) -> Vec<Vec2> {
+        let tile_aabb = AABB::new(Vec2::splat(32.0));
+        let aabb = AABB::new(Vec2::splat(16.0));
+        let mut walker = body(1.0, start);
+        walker.state.ground = true;
+
+        (0..ticks)
+            .map(|_| {
+                walker.old_position = walker.position;
+                walker.old_state = walker.state;
+                walker.state = MovingObjectState::default();
+                walker.velocity.value.x = speed;
+                walker.velocity.value.y -= GRAVITY_CONSTANT * DELTA;
+                walker.position.value += walker.velocity.value * DELTA;
+
+                for (position, slope) in tiles {
+                    let mut tile = body(0.0, *position);
+                    let collider = |slope| ColliderItem {
+                        layers: None,
+                        sensor: false,
+                        one_way_platform: false,
+                        drop_through: false,
+                        slope,
+                        material: None,
+                        gravity: None,
+                        is_static: false,
+                        sleeping: false,
+                    };
+                    correct_collisions(
+                        &mut walker,
+                        &aabb,
+                        &collider(None),
+                        &mut tile,
+                        &tile_aabb,
+                        &collider(slope.as_ref()),
+                        (false, true),
+                    );
+                }
+
+                let state = walker.state;
+                assert!(state.ground, "lost the ground at {}", walker.position.value);
+                assert!(
+                    !state.left && !state.right,
+                    "snagged at {}",
+                    walker.position.value
+                );
+                walker.position.value
+            })
+            .collect()
+    }
+
+    // Checks that the body always stood on the ground going up by `gradient` from x = 32 to 96
+    fn assert_on_surface(positions: &[Vec2], gradient: f32) {
+        let surface = |x: f32| 32.0 + ((x - 32.0) * gradient).clamp(0.0, 64.0);
+        for position in positions {
+            let bottom = position.y - 16.0;
+            let expected = surface(position.x - 16.0).max(surface(position.x + 16.0));
+            assert!(
+                (bottom - expected).abs() < 1e-3,
+                "bottom at {bottom} instead of {expected} at x {}",
+                position.x
+            );
+        }
+    }
+
+    #[test]
+    fn bodies_walk_up_and_down_45_degree_slopes() {
+        let tiles = [
+            (Vec2::ZERO, None),
+            (Vec2::new(64.0, 64.0), Some(Slope::RISING_45)),
+            (Vec2::new(128.0, 64.0), None),
+        ];
+
+        assert_on_surface(&walk(&tiles, Vec2::new(0.0, 48.0), 400.0, 20), 1.0);
+        assert_on_surface(&walk(&tiles, Vec2::new(128.0, 112.0), -400.0, 20), 1.0);
+    }
+
+    #[test]
+    fn bodies_walk_up_and_down_22_degree_slopes() {
+        let tiles = [
+            (Vec2::ZERO, None),
+            (Vec2::new(64.0, 64.0), Some(Slope::RISING_22_LOW)),
+            (Vec2::new(128.0, 64.0), Some(Slope::RISING_22_HIGH)),
+            (Vec2::new(192.0, 64.0), None),
+        ];
+
+        assert_on_surface(&walk(&tiles, Vec2::new(0.0, 48.0), 400.0, 30), 0.5);
+        assert_on_surface(&walk(&tiles, Vec2::new(192.0, 112.0), -400.0, 30), 0.5);
+    }
+
+    #[test]
+    fn fast_drops_land_on_the_slope_surface() {
+        let drop = |slope| {
+            swept_slope(
+                &AABB::new(Vec2::splat(4.0)),
+                Position::new(Vec2::new(64.0, 120.0)),
+                Position::new(Vec2::new(64.0, 40.0)),
+                &AABB::new(Vec2::splat(32.0)),
+                Position::new(Vec2::splat(64.0)),
+                slope,
+            )
+            .unwrap()
+        };
+
+        // the top of the aabb would be hit at 0.25, the surface under the right edge is at 68
+        let (time_of_impact, normal) = drop(&Slope::RISING_45);
+        assert!((time_of_impact - 0.6).abs() < 1e-3);
+        assert!((normal - Vec2::new(-1.0, 1.0).normalize()).length() < 1e-6);
+
+        let (time_of_impact, normal) = drop(&Slope::RISING_22_LOW);
+        assert!((time_of_impact - 0.825).abs() < 1e-3);
+        assert!((normal - Vec2::new(-0.5, 1.0).normalize()).length() < 1e-6);
+    }
+
+    #[test]
+    fn physics_time_only_advances_on_ticks() {
+        let mut world = physics_world();
+        world.insert_resource(PhysicsTime {
+            scale: 0.5,
+            ..default()
+        });
+
+        // half as fast, so only every second fixed update ticks
+        for _ in 0..4 {
+            world.run_system_once(advance_physics_time);
+        }
+        assert_eq!(
+            world.resource::<PhysicsTime>().elapsed_seconds(),
+            DELTA * 2.0
+        );
+
+        world.resource_mut::<PhysicsTime>().paused = true;
+        for _ in 0..4 {
+            world.run_system_once(advance_physics_time);
+        }
+        assert_eq!(
+            world.resource::<PhysicsTime>().elapsed_seconds(),
+            DELTA * 2.0
+        );
+
+        world.resource_mut::<PhysicsTime>().step();
+        world.run_system_once(advance_physics_time);
+        assert_eq!(
+            world.resource::<PhysicsTime>().elapsed_seconds(),
+            DELTA * 3.0
+        );
+    }
 }
@@ -0,0 +1,132 @@
+use std::f32::consts::TAU;
+
+use bevy::prelude::*;
+
+use crate::physics::{
+    apply_gravity, physics_running, update_physics, update_physics_broadphase, Contacts, Gravity,
+    MovingObject, PhysicsTime,
+};
+
+pub struct PlatformPlugin;
+impl Plugin for PlatformPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_type::<KinematicPlatform>().add_systems(
+            FixedUpdate,
+            (
+                move_platforms.after(apply_gravity).before(update_physics),
+                carry_riders
+                    .after(update_physics)
+                    .before(update_physics_broadphase),
+            )
+                .run_if(physics_running),
+        );
+    }
+}
+
+/// Moves a `MovingObject` without mass along a path. It ignores gravity, pushes other bodies out
+/// of the way and carries the ones standing on it
+#[derive(Component, Debug, Clone, Reflect)]
+#[reflect(Component)]
+pub enum KinematicPlatform {
+    /// Moves from point to point at a constant speed, starting over after the last one
+    Waypoints {
+        points: Vec<Vec2>,
+        speed: f32,
+        next: usize,
+    },
+    /// Swings around `origin`, `frequency` times per second
+    Sine {
+        origin: Vec2,
+        amplitude: Vec2,
+        frequency: f32,
+    },
+}
+impl KinematicPlatform {
+    pub const fn waypoints(points: Vec<Vec2>, speed: f32) -> Self {
+        Self::Waypoints {
+            points,
+            speed,
+            next: 0,
+        }
+    }
+
+    pub const fn sine(origin: Vec2, amplitude: Vec2, frequency: f32) -> Self {
+        Self::Sine {
+            origin,
+            amplitude,
+            frequency,
+        }
+    }
+}
+
+// Sets the velocity so the platform reaches the next point on its path during this tick
+fn move_platforms(
+    mut query: Query<(&mut KinematicPlatform, &mut MovingObject)>,
+    time: Res<Time>,
+    physics_time: Res<PhysicsTime>,
+) {
+    let delta = time.delta_seconds();
+    if delta == 0.0 {
+        return;
+    }
+
+    for (mut platform, mut moving_object) in &mut query {
+        let position = moving_object.position.value;
+
+        moving_object.velocity.value = match platform.as_mut() {
+            KinematicPlatform::Waypoints {
+                points,
+                speed,
+                next,
+            } => match points.get(*next) {
+                None => Vec2::ZERO,
+                Some(target) => {
+                    let to_target = *target - position;
+
+                    // head to the next point once this one is reached
+                    if to_target.length() <= *speed * delta {
+                        *next = (*next + 1) % points.len();
+                        to_target / delta
+                    } else {
+                        to_target.normalize() * *speed
+                    }
+                }
+            },
+            KinematicPlatform::Sine {
+                origin,
+                amplitude,
+                frequency,
+            } => {
+                // the time of the physics, so it slows down and pauses along with it
+                let target = *origin
+                    + *amplitude * (TAU * *frequency * physics_time.elapsed_seconds()).sin();
+                (target - position) / delta
+            }
+        };
+    }
+}
+
+// Moves everything that stood on a platform in the last tick along with it
+fn carry_riders(
+    mut query: Query<&mut MovingObject>,
+    gravities: Query<&Gravity>,
+    platforms: Query<(), With<KinematicPlatform>>,
+    contacts: Res<Contacts>,
+) {
+    for contact in contacts.iter() {
+        // only bodies standing on top get carried, relative to their gravity
+        let up = gravities.get(contact.a).map_or(Vec2::Y, Gravity::up);
+        if contact.normal.dot(up) <= 0.5 || !platforms.contains(contact.b) {
+            continue;
+        }
+
+        let Ok([mut rider, platform]) = query.get_many_mut([contact.a, contact.b]) else {
+            continue;
+        };
+        if rider.mass == 0.0 {
+            continue;
+        }
+
+        rider.position.value += platform.position.value - platform.old_position.value;
+    }
+}
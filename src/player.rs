@@ -1,12 +1,31 @@
 use crate::asset_loader::load_assets;
 use crate::boids::BoidParameters;
-use crate::map::TILE_SIZE;
-use crate::physics::{Gravity, MovingObject, MovingSpriteBundle, AABB, GRAVITY_CONSTANT};
+use crate::map::{KillZone, TILE_LAYER, TILE_SIZE};
+use crate::physics::{
+    CollisionLayers, Contacts, ContinuousCollision, DistanceJoint, DropThrough, ExternalImpulse,
+    Fluid, Gravity, JointAnchor, MovingObject, MovingSpriteBundle, OneWayPlatform, Sensor, AABB,
+    GRAVITY_CONSTANT,
+};
+use crate::rope::{despawn_rope, spawn_rope, RopeSegment};
+use crate::spatial_query::{SpatialQuery, SpatialQueryFilter};
 use bevy::prelude::*;
 
-const PLAYER_SPEED: f32 = 400.0;
+pub const PLAYER_SPEED: f32 = 400.0;
+// how fast the player speeds up and slows down, scaled by the friction of the ground
+pub const PLAYER_ACCELERATION: f32 = 8000.0;
 pub const PLAYER_JUMP_FORCE: f32 = 600.0;
-const PLAYER_TERMINAL_VELOCITY: f32 = 1000.0;
+pub const PLAYER_TERMINAL_VELOCITY: f32 = 1000.0;
+pub const PLAYER_LAYER: u32 = 1 << 1;
+// how long one way platforms are ignored after dropping through them
+const PLAYER_DROP_THROUGH_TIME: f32 = 0.25;
+// how far below the player the ground is looked for
+const GROUND_PROBE_DISTANCE: f32 = 2.0;
+const PLAYER_SWIM_SPEED: f32 = 250.0;
+// how fast the player speeds up and slows down in water
+const PLAYER_SWIM_ACCELERATION: f32 = 1500.0;
+// how far away the ceiling can be for the rope to reach it
+const PLAYER_ROPE_LENGTH: f32 = TILE_SIZE * 5.0;
+const PLAYER_ROPE_LINK_LENGTH: f32 = TILE_SIZE / 2.0;
 
 pub struct Playerplugin;
 impl Plugin for Playerplugin {
@@ -16,7 +35,15 @@ impl Plugin for Playerplugin {
             .register_type::<Stretching>()
             .register_type::<Player>()
             .add_systems(Startup, spawn_player.after(load_assets))
-            .add_systems(Update, movement_controls);
+            .add_systems(
+                Update,
+                (
+                    update_swimming.before(movement_controls),
+                    movement_controls,
+                    swing_controls.after(movement_controls),
+                    respawn_in_kill_zones,
+                ),
+            );
     }
 }
 
@@ -33,6 +60,7 @@ enum PlayerState {
     Walking,
     #[default]
     Jumping,
+    Swimming,
 }
 
 #[derive(Component, Clone, Debug, Default, Reflect)]
@@ -87,7 +115,7 @@ fn spawn_player(mut commands: Commands, asset_server: Res<AssetServer>) {
                 },
                 ..default()
             },
-            gravity: Gravity::new(GRAVITY_CONSTANT, PLAYER_TERMINAL_VELOCITY),
+            gravity: Gravity::new(Vec2::NEG_Y * GRAVITY_CONSTANT, PLAYER_TERMINAL_VELOCITY),
             aabb: AABB::new(Vec2::new(TILE_SIZE / 2.0, TILE_SIZE / 2.0)),
             moving_object: MovingObject {
                 mass: 1.0,
@@ -102,32 +130,80 @@ fn spawn_player(mut commands: Commands, asset_server: Res<AssetServer>) {
         PlayerState::Standing,
         Stretching::new(100.0, (TILE_SIZE / 2.0) * (TILE_SIZE / 2.0), 10.0, false),
         Jump::new(PLAYER_JUMP_FORCE),
+        // can reach PLAYER_TERMINAL_VELOCITY, which is fast enough to skip thin tiles
+        ContinuousCollision,
+        CollisionLayers::new(PLAYER_LAYER, CollisionLayers::ALL),
+        ExternalImpulse::default(),
     ));
 }
 
 // System -- Update
+type ControlledPlayer<'a> = (
+    Entity,
+    &'a mut MovingObject,
+    &'a mut ExternalImpulse,
+    &'a mut PlayerState,
+    &'a mut Sprite,
+    &'a mut AABB,
+    &'a mut Stretching,
+    &'a Jump,
+    &'a Player,
+    &'a Gravity,
+    Has<DropThrough>,
+);
+
+#[allow(clippy::too_many_arguments)]
 fn movement_controls(
-    mut query: Query<(
-        &mut MovingObject,
-        &mut PlayerState,
-        &mut Sprite,
-        &mut AABB,
-        &mut Stretching,
-        &Jump,
-        &Player,
-    )>,
+    mut commands: Commands,
+    mut query: Query<ControlledPlayer>,
     keyboard_input: Res<ButtonInput<KeyCode>>,
     time: Res<Time>,
     mut boid_params: ResMut<BoidParameters>,
+    spatial_query: SpatialQuery,
+    one_way_platforms: Query<(), With<OneWayPlatform>>,
+    contacts: Res<Contacts>,
+    bodies: Query<&MovingObject, Without<Player>>,
 ) {
-    let (mut moving_object, mut player_state, mut sprite, mut aabb, mut stretching, jump, player) =
-        query.single_mut();
+    let (
+        entity,
+        mut moving_object,
+        mut impulse,
+        mut player_state,
+        mut sprite,
+        mut aabb,
+        mut stretching,
+        jump,
+        player,
+        gravity,
+        dropping_through,
+    ) = query.single_mut();
+
+    // everything is relative to the gravity, so the controls keep working upside down
+    let up = gravity.up();
+    let right = gravity.right();
+    sprite.flip_y = up.y < 0.0;
+
+    // the grippiest surface the player is standing on
+    let ground_friction = contacts
+        .of(entity)
+        .filter(|contact| contact.normal.dot(up) > 0.0)
+        .map(|contact| contact.material.friction)
+        .reduce(f32::max)
+        .unwrap_or(1.0);
+
+    // pushing bodies slows the player down, like their momentum would
+    let pushed_mass = pushed_mass(&contacts, entity, right, |body| {
+        bodies.get(body).ok().map(|body| body.mass)
+    });
+    let speed = player.speed * moving_object.mass / (moving_object.mass + pushed_mass);
 
     match player_state.as_mut() {
         PlayerState::Standing | PlayerState::Walking => {
-            // left
+            // slippery ground makes turning slow, sticky ground makes walking slow
             move_horizontal(
-                player.speed,
+                right,
+                speed / ground_friction.max(1.0),
+                PLAYER_ACCELERATION * ground_friction.min(1.0) * time.delta_seconds(),
                 &keyboard_input,
                 &mut player_state,
                 &mut sprite,
@@ -137,13 +213,34 @@ fn movement_controls(
 
             // if jump key is pressed
             if keyboard_input.pressed(KeyCode::Space) {
-                moving_object.velocity.value.y += jump.force;
+                // drop through one way platforms instead when down is held as well
+                let on_one_way_platform = keyboard_input.pressed(KeyCode::KeyS)
+                    && spatial_query
+                        .cast_ray(
+                            moving_object.position.value,
+                            -up,
+                            aabb.halfsize.y + GROUND_PROBE_DISTANCE,
+                            &SpatialQueryFilter::new(CollisionLayers::default(), vec![entity]),
+                        )
+                        .is_some_and(|hit| one_way_platforms.contains(hit.entity));
+
+                if on_one_way_platform {
+                    commands
+                        .entity(entity)
+                        .insert(DropThrough::new(PLAYER_DROP_THROUGH_TIME));
+                } else {
+                    impulse.apply(up * jump.force * moving_object.mass);
+                }
                 *player_state = PlayerState::Jumping;
             }
         }
         PlayerState::Jumping => {
+            let vertical_velocity = moving_object.velocity.value.dot(up);
+
             move_horizontal(
-                player.speed * 0.7,
+                right,
+                speed * 0.7,
+                PLAYER_ACCELERATION * time.delta_seconds(),
                 &keyboard_input,
                 &mut player_state,
                 &mut sprite,
@@ -153,16 +250,39 @@ fn movement_controls(
 
             // if jump key is pressed
             if keyboard_input.pressed(KeyCode::Space) {
-                if moving_object.old_state.ground
-                    && moving_object.velocity.value.y > -5.0
-                    && moving_object.velocity.value.y < 5.0
+                // the jump only reaches the velocity in the next physics tick, dont queue it twice
+                let jump_queued = impulse.impulse.dot(up) > 0.0;
+
+                if !dropping_through
+                    && !jump_queued
+                    && moving_object.old_state.ground
+                    && vertical_velocity > -5.0
+                    && vertical_velocity < 5.0
                 {
-                    moving_object.velocity.value.y += jump.force;
+                    impulse.apply(up * jump.force * moving_object.mass);
                 }
-            } else if keyboard_input.just_released(KeyCode::Space)
-                && moving_object.velocity.value.y > 0.0
-            {
-                moving_object.velocity.value.y = 0.0;
+            } else if keyboard_input.just_released(KeyCode::Space) && vertical_velocity > 0.0 {
+                moving_object.velocity.value -= up * vertical_velocity;
+            }
+        }
+        PlayerState::Swimming => {
+            move_horizontal(
+                right,
+                PLAYER_SWIM_SPEED,
+                PLAYER_SWIM_ACCELERATION * time.delta_seconds(),
+                &keyboard_input,
+                &mut player_state,
+                &mut sprite,
+                &mut moving_object,
+                false,
+            );
+
+            // swim up or down, otherwise let the water carry the player
+            let max_speed_change = PLAYER_SWIM_ACCELERATION * time.delta_seconds();
+            if keyboard_input.pressed(KeyCode::Space) {
+                accelerate_towards(&mut moving_object, up, PLAYER_SWIM_SPEED, max_speed_change);
+            } else if keyboard_input.pressed(KeyCode::KeyS) {
+                accelerate_towards(&mut moving_object, up, -PLAYER_SWIM_SPEED, max_speed_change);
             }
         }
     }
@@ -205,8 +325,125 @@ fn movement_controls(
     }
 }
 
+type SwingingPlayer<'a> = (
+    Entity,
+    &'a MovingObject,
+    &'a Sprite,
+    &'a Gravity,
+    Option<&'a DistanceJoint>,
+);
+
+// Shoots a rope at the ceiling in front of the player, which they can swing from until letting go.
+// The rope is made of links, so it wraps around the tiles it swings past
+fn swing_controls(
+    mut commands: Commands,
+    query: Query<SwingingPlayer, With<Player>>,
+    ropes: Query<&DistanceJoint, With<RopeSegment>>,
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    spatial_query: SpatialQuery,
+) {
+    let (entity, moving_object, sprite, gravity, joint) = query.single();
+
+    if let Some(joint) = joint {
+        if keyboard_input.just_pressed(KeyCode::KeyE) || keyboard_input.just_pressed(KeyCode::Space)
+        {
+            commands.entity(entity).remove::<DistanceJoint>();
+            if let JointAnchor::Body(last) = joint.anchor {
+                despawn_rope(&mut commands, &ropes, last);
+            }
+        }
+        return;
+    }
+    if !keyboard_input.just_pressed(KeyCode::KeyE) {
+        return;
+    }
+
+    // diagonally up, in the direction the player looks
+    let forward = if sprite.flip_x {
+        -gravity.right()
+    } else {
+        gravity.right()
+    };
+    let hit = spatial_query.cast_ray(
+        moving_object.position.value,
+        gravity.up() + forward,
+        PLAYER_ROPE_LENGTH,
+        &SpatialQueryFilter::new(CollisionLayers::new(PLAYER_LAYER, TILE_LAYER), vec![entity]),
+    );
+    let Some(hit) = hit else {
+        return;
+    };
+
+    // the player hangs from the last link, one link length below it
+    let links = (hit.distance / PLAYER_ROPE_LINK_LENGTH).ceil().max(2.0) as usize;
+    let link_length = hit.distance / links as f32;
+    let last = spawn_rope(
+        &mut commands,
+        JointAnchor::Point(hit.point),
+        hit.point,
+        hit.point.lerp(
+            moving_object.position.value,
+            (links - 1) as f32 / links as f32,
+        ),
+        links - 1,
+    );
+    commands
+        .entity(entity)
+        .insert(DistanceJoint::rope(JointAnchor::Body(last), link_length));
+}
+
+// Swims while inside a fluid, and falls when leaving it
+fn update_swimming(
+    mut query: Query<(Entity, &mut PlayerState), With<Player>>,
+    fluids: Query<&Sensor, With<Fluid>>,
+) {
+    let (entity, mut player_state) = query.single_mut();
+
+    let in_fluid = fluids
+        .iter()
+        .any(|sensor| sensor.occupants().contains(&entity));
+
+    let swimming = matches!(*player_state, PlayerState::Swimming);
+    if in_fluid && !swimming {
+        *player_state = PlayerState::Swimming;
+    } else if !in_fluid && swimming {
+        *player_state = PlayerState::Jumping;
+    }
+}
+
+fn respawn_in_kill_zones(
+    mut commands: Commands,
+    mut query: Query<(Entity, &mut MovingObject, Option<&DistanceJoint>), With<Player>>,
+    ropes: Query<&DistanceJoint, With<RopeSegment>>,
+    kill_zones: Query<&Sensor, With<KillZone>>,
+) {
+    let (entity, mut moving_object, joint) = query.single_mut();
+
+    if kill_zones
+        .iter()
+        .any(|sensor| sensor.occupants().contains(&entity))
+    {
+        // back to the spawn point, without any leftover velocity
+        *moving_object = MovingObject {
+            mass: moving_object.mass,
+            ..default()
+        };
+
+        // let go of the rope, it would be dragged across the map otherwise
+        if let Some(joint) = joint {
+            commands.entity(entity).remove::<DistanceJoint>();
+            if let JointAnchor::Body(last) = joint.anchor {
+                despawn_rope(&mut commands, &ropes, last);
+            }
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
 fn move_horizontal(
+    right: Vec2,
     movement_speed: f32,
+    max_speed_change: f32,
     keyboard_input: &Res<ButtonInput<KeyCode>>,
     player_state: &mut PlayerState,
     sprite: &mut Sprite,
@@ -218,7 +455,7 @@ fn move_horizontal(
         if change_state {
             *player_state = PlayerState::Standing;
         }
-        moving_object.velocity.value.x = 0.0;
+        accelerate_towards(moving_object, right, 0.0, max_speed_change);
     }
     // left
     else if keyboard_input.pressed(KeyCode::KeyA) {
@@ -226,9 +463,9 @@ fn move_horizontal(
             *player_state = PlayerState::Walking;
         }
         if moving_object.state.left {
-            moving_object.velocity.value.x = 0.0;
+            moving_object.velocity.value -= right * moving_object.velocity.value.dot(right);
         } else {
-            moving_object.velocity.value.x = -movement_speed;
+            accelerate_towards(moving_object, right, -movement_speed, max_speed_change);
             sprite.flip_x = true;
         }
         // right
@@ -237,9 +474,9 @@ fn move_horizontal(
             *player_state = PlayerState::Walking;
         }
         if moving_object.state.right {
-            moving_object.velocity.value.x = 0.0;
+            moving_object.velocity.value -= right * moving_object.velocity.value.dot(right);
         } else {
-            moving_object.velocity.value.x = movement_speed;
+            accelerate_towards(moving_object, right, movement_speed, max_speed_change);
             sprite.flip_x = false;
         }
     }
@@ -249,3 +486,31 @@ fn move_horizontal(
         *player_state = PlayerState::Jumping;
     }
 }
+
+/// Changes the velocity along `axis` towards `speed`, by at most `max_speed_change`
+pub fn accelerate_towards(
+    moving_object: &mut MovingObject,
+    axis: Vec2,
+    speed: f32,
+    max_speed_change: f32,
+) {
+    let current_speed = moving_object.velocity.value.dot(axis);
+    moving_object.velocity.value +=
+        axis * (speed - current_speed).clamp(-max_speed_change, max_speed_change);
+}
+
+/// The mass of the bodies `entity` touched from the side in the last physics tick, `mass_of` returns
+/// the mass of a body. Tiles have no mass, so walls dont count
+pub fn pushed_mass(
+    contacts: &Contacts,
+    entity: Entity,
+    right: Vec2,
+    mass_of: impl Fn(Entity) -> Option<f32>,
+) -> f32 {
+    contacts
+        .of(entity)
+        .filter(|contact| contact.normal.dot(right) != 0.0)
+        .filter_map(|contact| mass_of(contact.b))
+        .filter(|mass| *mass > 0.0)
+        .sum()
+}
@@ -1,139 +1,717 @@
-use crate::physics::{collides, intersects, MovingObject, Position, AABB};
-use bevy::prelude::*;
+use std::hash::Hash;
+
+use crate::{
+    broadphase::{
+        distance, overlapping, push_nearest, touches, within, Broadphase, BroadphaseObject, Shape,
+    },
+    physics::{Position, AABB},
+};
+use bevy::{prelude::*, utils::HashMap};
+
+// nodes this deep dont subdivide anymore, they keep more objects than their capacity instead
+const MAX_DEPTH: usize = 16;
+// nodes this small dont subdivide anymore either, so many objects at the same position cant make
+// the tree recurse until the halfsize underflows
+const MIN_HALFSIZE: f32 = 1.0;
+// the root is always the first node
+const ROOT: usize = 0;
 
 #[derive(Debug)]
-pub struct Quadtree {
+struct Node<P, S> {
     boundary: AABB,
     center: Position,
-    capacity: usize,
-    objects: Vec<(Entity, Option<AABB>, Position)>,
-    divided: bool,
-    // Children
-    nw: Option<Box<Self>>,
-    ne: Option<Box<Self>>,
-    sw: Option<Box<Self>>,
-    se: Option<Box<Self>>,
+    depth: usize,
+    parent: Option<usize>,
+    objects: Vec<(P, S)>,
+    // northwest, northeast, southwest, southeast
+    children: Option<[usize; 4]>,
 }
-impl Quadtree {
-    pub const fn new(boundary: AABB, center: Vec2, capacity: usize) -> Self {
+impl<P, S> Node<P, S> {
+    const fn new(boundary: AABB, center: Vec2, depth: usize, parent: Option<usize>) -> Self {
         Self {
             boundary,
             center: Position::new(center),
-            capacity,
+            depth,
+            parent,
             objects: Vec::new(),
-            divided: false,
-            nw: None,
-            ne: None,
-            sw: None,
-            se: None,
+            children: None,
         }
     }
+}
+impl<P, S> Shape for Node<P, S> {
+    fn bounds(&self) -> (Vec2, Vec2) {
+        (self.center.value, self.boundary.halfsize)
+    }
+}
 
-    pub fn subdivide(&mut self) {
-        let half_boundary = self.boundary.halfsize / 2.0;
-        let center = self.center.value;
-        let halfsize = Vec2::new(half_boundary.x, half_boundary.y);
+/// Stores payloads, like entities, by the `Shape` around them. It doesnt need a `World`, so it can
+/// be used by tools and tests as well.
+/// The nodes point at each other by their index, so the tree can be kept around and changed
+/// instead of being built again every frame
+#[derive(Debug)]
+pub struct Quadtree<P, S> {
+    capacity: usize,
+    nodes: Vec<Node<P, S>>,
+    // nodes that were merged away, reused when subdividing
+    free: Vec<usize>,
+    // the leaves each payload is stored in
+    locations: HashMap<P, Vec<usize>>,
+}
+impl<P: Copy + Eq + Hash + Ord, S: Shape + Clone> Quadtree<P, S> {
+    pub fn new(boundary: AABB, center: Vec2, capacity: usize) -> Self {
+        Self {
+            capacity,
+            nodes: vec![Node::new(boundary, center, 0, None)],
+            free: Vec::new(),
+            locations: HashMap::new(),
+        }
+    }
 
-        // Northwest
-        let nw = AABB { halfsize };
-        let nw_center = Vec2::new(center.x - half_boundary.x, center.y + half_boundary.y);
-        self.nw = Some(Box::new(Self::new(nw, nw_center, self.capacity)));
+    // Leaves that are too deep or too small overflow instead
+    fn can_subdivide(&self, node: usize) -> bool {
+        let node = &self.nodes[node];
+        node.depth < MAX_DEPTH && node.boundary.halfsize.min_element() / 2.0 >= MIN_HALFSIZE
+    }
 
-        // Northeast
-        let ne = AABB { halfsize };
-        let ne_center = Vec2::new(center.x + half_boundary.x, center.y + half_boundary.y);
-        self.ne = Some(Box::new(Self::new(ne, ne_center, self.capacity)));
+    fn add_node(&mut self, node: Node<P, S>) -> usize {
+        if let Some(index) = self.free.pop() {
+            self.nodes[index] = node;
+            index
+        } else {
+            self.nodes.push(node);
+            self.nodes.len() - 1
+        }
+    }
 
-        // Southwest
-        let sw = AABB { halfsize };
-        let sw_center = Vec2::new(center.x - half_boundary.x, center.y - half_boundary.y);
-        self.sw = Some(Box::new(Self::new(sw, sw_center, self.capacity)));
+    fn subdivide(&mut self, node: usize) {
+        let halfsize = self.nodes[node].boundary.halfsize / 2.0;
+        let center = self.nodes[node].center.value;
+        let depth = self.nodes[node].depth + 1;
 
-        // Southeast
-        let se = AABB { halfsize };
-        let se_center = Vec2::new(center.x + half_boundary.x, center.y - half_boundary.y);
-        self.se = Some(Box::new(Self::new(se, se_center, self.capacity)));
+        let children = [
+            Vec2::new(-1.0, 1.0),
+            Vec2::new(1.0, 1.0),
+            Vec2::new(-1.0, -1.0),
+            Vec2::new(1.0, -1.0),
+        ]
+        .map(|direction| {
+            self.add_node(Node::new(
+                AABB::new(halfsize),
+                center + direction * halfsize,
+                depth,
+                Some(node),
+            ))
+        });
+        self.nodes[node].children = Some(children);
 
-        self.divided = true;
+        // redistribute the objects to the children
+        let objects = std::mem::take(&mut self.nodes[node].objects);
+        for (payload, shape) in objects {
+            if let Some(leaves) = self.locations.get_mut(&payload) {
+                leaves.retain(|leaf| *leaf != node);
+            }
+            self.insert_into(node, payload, shape);
+        }
+    }
 
-        // implement redistrubuting objects
-        let objects = std::mem::take(&mut self.objects);
+    /// Returns false if the shape is outside of the boundary, it isnt stored then
+    pub fn insert(&mut self, payload: P, shape: S) -> bool {
+        self.insert_into(ROOT, payload, shape)
+    }
 
-        for (entity, aabb, position) in objects {
-            self.insert(entity, aabb, position);
+    fn insert_into(&mut self, node: usize, payload: P, shape: S) -> bool {
+        // Check if the shape intersects the nodes boundary. Objects exactly on the edge between
+        // two nodes are stored in both, so no query going through that edge can miss them
+        if !touches(&self.nodes[node].boundary, self.nodes[node].center, &shape) {
+            return false;
         }
-    }
 
-    pub fn insert(&mut self, entity: Entity, aabb: Option<AABB>, position: Position) -> bool {
-        match aabb {
-            Some(ref aabb) => {
-                // Check if the aabb intersects the nodes boundary
-                if !collides(&self.boundary, self.center, aabb, position) {
-                    return false;
-                }
-            }
+        let children = match self.nodes[node].children {
+            Some(children) => children,
             None => {
-                // Check if the point intersects the nodes boundary
-                if !intersects(&self.boundary, self.center, position) {
-                    return false;
+                // add it to the objects if the leaf still has capacity, or cant be subdivided
+                if self.nodes[node].objects.len() < self.capacity || !self.can_subdivide(node) {
+                    self.nodes[node].objects.push((payload, shape));
+                    self.locations.entry(payload).or_default().push(node);
+                    return true;
                 }
+                // if it doesnt have capacity anymore, subdivide
+                self.subdivide(node);
+                self.nodes[node].children.unwrap()
             }
+        };
+
+        // insert it into any child it intersects with
+        let mut inserted = false;
+        for child in children {
+            inserted |= self.insert_into(child, payload, shape.clone());
+        }
+        inserted
+    }
+
+    /// Removes the payload from every leaf it is in, merging nodes whose children got too empty
+    pub fn remove(&mut self, payload: P) -> bool {
+        let Some(leaves) = self.locations.remove(&payload) else {
+            return false;
+        };
+
+        for leaf in &leaves {
+            self.nodes[*leaf]
+                .objects
+                .retain(|(other, _)| *other != payload);
         }
-        // If the node hasnt been subdivided yet
-        if !self.divided {
-            // and it still has capacity
-            if self.objects.len() < self.capacity {
-                // add it to the objects
-                self.objects.push((entity, aabb, position));
-                return true;
+        for leaf in leaves {
+            if let Some(parent) = self.nodes[leaf].parent {
+                self.merge(parent);
             }
-            // if it doesnt have capacity anymore, subdivide
-            self.subdivide();
         }
+        true
+    }
 
-        // insert it into any child it intersects with// Define an array of mutable references to each quadrant
-        let quadrants = [&mut self.nw, &mut self.ne, &mut self.sw, &mut self.se];
+    /// Moves the payload to the leaves its new shape touches. Small moves usually stay in the same
+    /// leaves, then only the stored shape changes
+    pub fn update(&mut self, payload: P, shape: S) -> bool {
+        let Some(current) = self.locations.get(&payload) else {
+            return self.insert(payload, shape);
+        };
 
-        let mut inserted = false;
+        // a shape strictly inside a node cant touch any leaf outside of it, so only the leaves under
+        // the smallest such node around one of the current leaves have to be checked
+        let (center, halfsize) = shape.bounds();
+        let mut node = current[0];
+        while !((center - self.nodes[node].center.value).abs() + halfsize)
+            .cmplt(self.nodes[node].boundary.halfsize)
+            .all()
+        {
+            match self.nodes[node].parent {
+                Some(parent) => node = parent,
+                None => break,
+            }
+        }
 
-        for quadrant in quadrants {
-            if let Some(quadrant_ref) = quadrant.as_mut() {
-                inserted |= quadrant_ref.insert(entity, aabb.clone(), position);
+        // usually that is its only leaf, which is the same one
+        let same_leaves = if self.nodes[node].children.is_none() {
+            current.len() == 1 && current[0] == node
+        } else {
+            let mut leaves = Vec::new();
+            self.leaves_touching(node, &shape, &mut leaves);
+            !leaves.is_empty()
+                && leaves.len() == current.len()
+                && leaves.iter().all(|leaf| current.contains(leaf))
+        };
+        if same_leaves {
+            for leaf in current {
+                for object in &mut self.nodes[*leaf].objects {
+                    if object.0 == payload {
+                        object.1 = shape.clone();
+                    }
+                }
             }
+            return true;
         }
 
-        inserted
+        self.remove(payload);
+        self.insert(payload, shape)
+    }
+
+    // Pushes the leaves under `node` the shape touches, without changing the tree
+    fn leaves_touching(&self, node: usize, shape: &S, leaves: &mut Vec<usize>) {
+        if !touches(&self.nodes[node].boundary, self.nodes[node].center, shape) {
+            return;
+        }
+        match self.nodes[node].children {
+            Some(children) => {
+                for child in children {
+                    self.leaves_touching(child, shape, leaves);
+                }
+            }
+            None => leaves.push(node),
+        }
+    }
+
+    // Turns the node back into a leaf, if all its children are leaves and it can hold their objects
+    fn merge(&mut self, node: usize) {
+        let Some(children) = self.nodes[node].children else {
+            return;
+        };
+        if children
+            .iter()
+            .any(|child| self.nodes[*child].children.is_some())
+        {
+            return;
+        }
+
+        let mut objects: Vec<_> = children
+            .iter()
+            .flat_map(|child| self.nodes[*child].objects.iter().cloned())
+            .collect();
+        objects.sort_unstable_by_key(|(payload, _)| *payload);
+        objects.dedup_by_key(|(payload, _)| *payload);
+        if objects.len() > self.capacity {
+            return;
+        }
+
+        for child in children {
+            self.nodes[child].objects.clear();
+            self.free.push(child);
+        }
+        for (payload, _) in &objects {
+            let leaves = self.locations.get_mut(payload).unwrap();
+            leaves.retain(|leaf| !children.contains(leaf));
+            leaves.push(node);
+        }
+        self.nodes[node].children = None;
+        self.nodes[node].objects = objects;
+
+        // the parent might be able to merge now as well
+        if let Some(parent) = self.nodes[node].parent {
+            self.merge(parent);
+        }
+    }
+
+    /// Pushes every object in the leaves the range touches. They dont have to overlap the range,
+    /// and the same object can be pushed more than once
+    pub fn query_candidates<'a>(
+        &'a self,
+        range: &AABB,
+        position: Position,
+        found: &mut Vec<&'a (P, S)>,
+    ) {
+        self.query_node(ROOT, range, position, found);
+    }
+
+    /// Pushes every object overlapping or touching the range once
+    pub fn query_objects<'a>(
+        &'a self,
+        range: &AABB,
+        position: Position,
+        found: &mut Vec<&'a (P, S)>,
+    ) {
+        let mut candidates = Vec::new();
+        self.query_candidates(range, position, &mut candidates);
+        found.extend(overlapping(candidates, range, position));
     }
 
-    pub fn query(&self, range: &AABB, position: Position, found: &mut Vec<Entity>) {
+    /// Pushes every payload overlapping or touching the range once
+    pub fn query(&self, range: &AABB, position: Position, found: &mut Vec<P>) {
+        let mut objects = Vec::new();
+        self.query_objects(range, position, &mut objects);
+        found.extend(objects.into_iter().map(|(payload, _)| *payload));
+    }
+
+    /// Pushes every payload whose shape contains the point once
+    pub fn query_point(&self, point: Vec2, found: &mut Vec<P>) {
+        self.query(&AABB::default(), Position::new(point), found);
+    }
+
+    /// Pushes every payload whose shape is at most `radius` away from `center` once
+    pub fn query_radius(&self, center: Vec2, radius: f32, found: &mut Vec<P>) {
+        let mut candidates = Vec::new();
+        self.query_candidates(
+            &AABB::new(Vec2::splat(radius)),
+            Position::new(center),
+            &mut candidates,
+        );
+        found.extend(
+            within(candidates, center, radius)
+                .into_iter()
+                .map(|(payload, _)| *payload),
+        );
+    }
+
+    /// Pushes the `k` payloads closest to `point` that are at most `max_distance` away from it,
+    /// nearest first, along with their distance
+    pub fn nearest(&self, point: Vec2, k: usize, max_distance: f32, found: &mut Vec<(P, f32)>) {
+        let mut nearest = Vec::new();
+        self.nearest_in_node(ROOT, point, k, max_distance, &mut nearest);
+        found.extend(nearest);
+    }
+
+    /// Calls `f` with the center and halfsize of every node, parents before their children
+    pub fn for_each_node(&self, mut f: impl FnMut(Vec2, Vec2)) {
+        let mut stack = vec![ROOT];
+        while let Some(node) = stack.pop() {
+            let current = &self.nodes[node];
+            f(current.center.value, current.boundary.halfsize);
+            stack.extend(current.children.into_iter().flatten());
+        }
+    }
+
+    fn query_node<'a>(
+        &'a self,
+        node: usize,
+        range: &AABB,
+        position: Position,
+        found: &mut Vec<&'a (P, S)>,
+    ) {
+        let current = &self.nodes[node];
         // dont do anything if the range doesnt intersect with the nodes boundary
-        if !collides(&self.boundary, self.center, range, position) {
+        if !touches(range, position, current) {
             return;
         }
 
-        // query child nodes
-        if self.divided {
-            self.nw.as_ref().unwrap().query(range, position, found);
-            self.ne.as_ref().unwrap().query(range, position, found);
-            self.sw.as_ref().unwrap().query(range, position, found);
-            self.se.as_ref().unwrap().query(range, position, found);
-        } else {
+        match current.children {
+            // query child nodes
+            Some(children) => {
+                for child in children {
+                    self.query_node(child, range, position, found);
+                }
+            }
             // if it hasnt been divided, push all objects to found
-            for (entity, _, _) in &self.objects {
-                found.push(*entity);
+            None => found.extend(&current.objects),
+        }
+    }
+
+    // Visits the closest children first, and skips nodes further away than the current `k`th
+    // nearest object
+    fn nearest_in_node(
+        &self,
+        node: usize,
+        point: Vec2,
+        k: usize,
+        max_distance: f32,
+        nearest: &mut Vec<(P, f32)>,
+    ) {
+        let current = &self.nodes[node];
+        let limit = match nearest.get(k.wrapping_sub(1)) {
+            Some((_, distance)) => *distance,
+            None => max_distance,
+        };
+        if k == 0 || distance(point, current) > limit {
+            return;
+        }
+
+        match current.children {
+            Some(children) => {
+                let mut children =
+                    children.map(|child| (child, distance(point, &self.nodes[child])));
+                children.sort_unstable_by(|a, b| a.1.total_cmp(&b.1));
+                for (child, _) in children {
+                    self.nearest_in_node(child, point, k, max_distance, nearest);
+                }
+            }
+            None => {
+                for (payload, shape) in &current.objects {
+                    let distance = distance(point, shape);
+                    if distance <= max_distance {
+                        push_nearest(nearest, k, *payload, distance);
+                    }
+                }
             }
         }
     }
 }
 
-pub fn build_quadtree<'a, T, I, F>(items: I, aabb: &AABB, capacity: usize, transform: F) -> Quadtree
-where
-    I: IntoIterator<Item = T>,
-    F: Fn(T) -> (Option<&'a AABB>, &'a MovingObject, Entity),
-{
-    let mut quadtree = Quadtree::new(aabb.clone(), Vec2::ZERO, capacity);
-    items.into_iter().map(transform).for_each(|item| {
-        quadtree.insert(item.2, item.0.cloned(), item.1.position);
-    });
-    quadtree
+impl Broadphase for Quadtree<Entity, (Option<AABB>, Position)> {
+    fn insert(&mut self, entity: Entity, aabb: Option<AABB>, position: Position) {
+        Self::insert(self, entity, (aabb, position));
+    }
+
+    fn remove(&mut self, entity: Entity) {
+        Self::remove(self, entity);
+    }
+
+    fn update(&mut self, entity: Entity, aabb: Option<AABB>, position: Position) {
+        Self::update(self, entity, (aabb, position));
+    }
+
+    fn query_candidates<'a>(
+        &'a self,
+        range: &AABB,
+        position: Position,
+        found: &mut Vec<&'a BroadphaseObject>,
+    ) {
+        Self::query_candidates(self, range, position, found);
+    }
+
+    fn query_objects<'a>(
+        &'a self,
+        range: &AABB,
+        position: Position,
+        found: &mut Vec<&'a BroadphaseObject>,
+    ) {
+        Self::query_objects(self, range, position, found);
+    }
+
+    fn query(&self, range: &AABB, position: Position, found: &mut Vec<Entity>) {
+        Self::query(self, range, position, found);
+    }
+
+    fn query_point(&self, point: Vec2, found: &mut Vec<Entity>) {
+        Self::query_point(self, point, found);
+    }
+
+    fn query_radius(&self, center: Vec2, radius: f32, found: &mut Vec<Entity>) {
+        Self::query_radius(self, center, radius, found);
+    }
+
+    fn nearest(&self, point: Vec2, k: usize, max_distance: f32, found: &mut Vec<(Entity, f32)>) {
+        Self::nearest(self, point, k, max_distance, found);
+    }
+
+    fn for_each_node(&self, f: &mut dyn FnMut(Vec2, Vec2)) {
+        Self::for_each_node(self, f);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    const BOUNDARY: f32 = 100.0;
+
+    // Coordinates on a coarse grid, so objects often share positions and lie on node edges
+    fn coordinate() -> impl Strategy<Value = f32> {
+        prop_oneof![
+            (-10..=10).prop_map(|value| value as f32 * BOUNDARY / 10.0),
+            -BOUNDARY..=BOUNDARY,
+        ]
+    }
+
+    fn position() -> impl Strategy<Value = Position> {
+        (coordinate(), coordinate()).prop_map(|(x, y)| Position::new(Vec2::new(x, y)))
+    }
+
+    // Points or aabbs of any size, including empty ones
+    fn object() -> impl Strategy<Value = (Option<AABB>, Position)> {
+        let aabb = prop_oneof![
+            Just(None),
+            (0.0..20.0f32, 0.0..20.0f32).prop_map(|(x, y)| Some(AABB::new(Vec2::new(x, y)))),
+        ];
+        (aabb, position())
+    }
+
+    // The payloads are the indices of the objects
+    type TestQuadtree = Quadtree<usize, (Option<AABB>, Position)>;
+
+    fn build(objects: &[(Option<AABB>, Position)], capacity: usize) -> TestQuadtree {
+        let mut quadtree = Quadtree::new(AABB::new(Vec2::splat(BOUNDARY)), Vec2::ZERO, capacity);
+        for (index, object) in objects.iter().enumerate() {
+            quadtree.insert(index, object.clone());
+        }
+        quadtree
+    }
+
+    fn depth(quadtree: &TestQuadtree) -> usize {
+        quadtree
+            .nodes
+            .iter()
+            .enumerate()
+            .filter(|(index, _)| !quadtree.free.contains(index))
+            .map(|(_, node)| node.depth)
+            .max()
+            .unwrap()
+    }
+
+    #[test]
+    fn many_objects_at_the_same_position() {
+        let objects = vec![(None, Position::new(Vec2::splat(12.5))); 1000];
+        let quadtree = build(&objects, 2);
+
+        assert!(depth(&quadtree) <= MAX_DEPTH);
+
+        let mut found = Vec::new();
+        quadtree.query(
+            &AABB::new(Vec2::ONE),
+            Position::new(Vec2::splat(12.5)),
+            &mut found,
+        );
+        assert_eq!(found.len(), 1000);
+    }
+
+    #[test]
+    fn empty_boundary() {
+        let mut quadtree: TestQuadtree = Quadtree::new(AABB::default(), Vec2::ZERO, 2);
+        for index in 0..100 {
+            quadtree.insert(index, (None, Position::default()));
+        }
+
+        assert_eq!(depth(&quadtree), 0);
+    }
+
+    proptest! {
+        #[test]
+        fn insert_terminates_within_limits(
+            objects in prop::collection::vec(object(), 0..200),
+            capacity in 1..4usize,
+        ) {
+            let quadtree = build(&objects, capacity);
+
+            prop_assert!(depth(&quadtree) <= MAX_DEPTH);
+        }
+
+        #[test]
+        fn query_finds_exactly_the_overlapping_objects(
+            objects in prop::collection::vec(object(), 0..200),
+            capacity in 1..4usize,
+            range in (0.0..50.0f32, 0.0..50.0f32).prop_map(|(x, y)| AABB::new(Vec2::new(x, y))),
+            range_position in position(),
+        ) {
+            let quadtree = build(&objects, capacity);
+
+            let mut found = Vec::new();
+            quadtree.query(&range, range_position, &mut found);
+
+            let expected: Vec<_> = objects
+                .iter()
+                .enumerate()
+                .filter(|(_, object)| touches(&range, range_position, *object))
+                .map(|(index, _)| index)
+                .collect();
+            prop_assert_eq!(found, expected);
+        }
+
+        #[test]
+        fn slightly_moved_objects_are_found_where_they_are_now(
+            objects in prop::collection::vec(object(), 0..200),
+            capacity in 1..4usize,
+            offsets in prop::collection::vec((-2.0..2.0f32, -2.0..2.0f32), 200),
+            range in (0.0..50.0f32, 0.0..50.0f32).prop_map(|(x, y)| AABB::new(Vec2::new(x, y))),
+            range_position in position(),
+        ) {
+            let mut quadtree = build(&objects, capacity);
+
+            // most of them stay in the same leaves, only their stored shape changes. They stay inside
+            // the boundary, objects outside of it arent stored
+            let objects: Vec<_> = objects
+                .into_iter()
+                .zip(offsets)
+                .map(|((aabb, position), (x, y))| {
+                    let moved = (position.value + Vec2::new(x, y)).clamp(Vec2::splat(-BOUNDARY), Vec2::splat(BOUNDARY));
+                    (aabb, Position::new(moved))
+                })
+                .collect();
+            for (index, object) in objects.iter().enumerate() {
+                quadtree.update(index, object.clone());
+            }
+
+            let mut found = Vec::new();
+            quadtree.query(&range, range_position, &mut found);
+
+            let expected: Vec<_> = objects
+                .iter()
+                .enumerate()
+                .filter(|(_, object)| touches(&range, range_position, *object))
+                .map(|(index, _)| index)
+                .collect();
+            prop_assert_eq!(found, expected);
+        }
+
+        #[test]
+        fn query_point_finds_the_objects_containing_it(
+            objects in prop::collection::vec(object(), 0..200),
+            capacity in 1..4usize,
+            point in position(),
+        ) {
+            let quadtree = build(&objects, capacity);
+
+            let mut found = Vec::new();
+            quadtree.query_point(point.value, &mut found);
+
+            let expected: Vec<_> = objects
+                .iter()
+                .enumerate()
+                .filter(|(_, object)| distance(point.value, *object) == 0.0)
+                .map(|(index, _)| index)
+                .collect();
+            prop_assert_eq!(found, expected);
+        }
+
+        #[test]
+        fn query_radius_finds_the_objects_within_it(
+            objects in prop::collection::vec(object(), 0..200),
+            capacity in 1..4usize,
+            center in position(),
+            radius in 0.0..50.0f32,
+        ) {
+            let quadtree = build(&objects, capacity);
+
+            let mut found = Vec::new();
+            quadtree.query_radius(center.value, radius, &mut found);
+
+            let expected: Vec<_> = objects
+                .iter()
+                .enumerate()
+                .filter(|(_, object)| distance(center.value, *object) <= radius)
+                .map(|(index, _)| index)
+                .collect();
+            prop_assert_eq!(found, expected);
+        }
+
+        #[test]
+        fn nearest_finds_the_closest_objects(
+            objects in prop::collection::vec(object(), 0..200),
+            capacity in 1..4usize,
+            point in position(),
+            k in 0..20usize,
+            max_distance in prop_oneof![Just(f32::INFINITY), 0.0..100.0f32],
+        ) {
+            let quadtree = build(&objects, capacity);
+
+            let mut found = Vec::new();
+            quadtree.nearest(point.value, k, max_distance, &mut found);
+
+            let mut distances: Vec<_> = objects
+                .iter()
+                .map(|object| distance(point.value, object))
+                .filter(|distance| *distance <= max_distance)
+                .collect();
+            distances.sort_unstable_by(f32::total_cmp);
+            distances.truncate(k);
+
+            // objects at the same distance can be found in any order
+            prop_assert_eq!(found.iter().map(|(_, distance)| *distance).collect::<Vec<_>>(), distances);
+            for (index, found_distance) in &found {
+                prop_assert_eq!(distance(point.value, &objects[*index]), *found_distance);
+            }
+            let mut indices: Vec<_> = found.iter().map(|(index, _)| *index).collect();
+            indices.sort_unstable();
+            indices.dedup();
+            prop_assert_eq!(indices.len(), found.len());
+        }
+
+        #[test]
+        fn removed_objects_are_never_found(
+            objects in prop::collection::vec(object(), 0..200),
+            capacity in 1..4usize,
+            removed in prop::collection::vec(any::<prop::sample::Index>(), 0..100),
+            moved in prop::collection::vec((any::<prop::sample::Index>(), object()), 0..50),
+        ) {
+            prop_assume!(!objects.is_empty());
+            let mut quadtree = build(&objects, capacity);
+            let mut objects: Vec<_> = objects.into_iter().map(Some).collect();
+
+            for (index, object) in moved {
+                let index = index.index(objects.len());
+                quadtree.update(index, object.clone());
+                objects[index] = Some(object);
+            }
+            for index in removed {
+                let index = index.index(objects.len());
+                quadtree.remove(index);
+                objects[index] = None;
+            }
+
+            let range = AABB::new(Vec2::splat(BOUNDARY));
+            let mut found = Vec::new();
+            quadtree.query(&range, Position::default(), &mut found);
+
+            for (index, object) in objects.iter().enumerate() {
+                prop_assert_eq!(object.is_some(), found.contains(&index));
+                if let Some((aabb, position)) = object {
+                    let mut found = Vec::new();
+                    quadtree.query(&aabb.clone().unwrap_or_default(), *position, &mut found);
+                    prop_assert!(found.contains(&index));
+                }
+            }
+
+            // once everything is gone the tree merges back into the root
+            for index in 0..objects.len() {
+                quadtree.remove(index);
+            }
+            prop_assert_eq!(depth(&quadtree), 0);
+            prop_assert!(quadtree.locations.is_empty());
+        }
+    }
 }
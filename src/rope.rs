@@ -0,0 +1,115 @@
+use bevy::prelude::*;
+
+use crate::{
+    map::TILE_LAYER,
+    physics::{
+        CollisionLayers, DistanceJoint, Gravity, JointAnchor, MovingObject, MovingSpriteBundle,
+        Position, AABB, GRAVITY_CONSTANT,
+    },
+};
+
+// ropes only collide with tiles, so they dont get tangled up with what hangs from them
+pub const ROPE_LAYER: u32 = 1 << 3;
+const ROPE_SEGMENT_SIZE: f32 = 6.0;
+const ROPE_SEGMENT_MASS: f32 = 0.1;
+const ROPE_TERMINAL_VELOCITY: f32 = 1000.0;
+const ROPE_COLOR: Color = Color::rgb(0.55, 0.4, 0.25);
+
+pub struct RopePlugin;
+impl Plugin for RopePlugin {
+    fn build(&self, app: &mut App) {
+        app.register_type::<RopeSegment>()
+            .add_systems(Update, draw_joints);
+    }
+}
+
+/// A link of a rope, hanging from the link before it
+#[derive(Component, Debug, Default, Reflect)]
+#[reflect(Component)]
+pub struct RopeSegment;
+
+/// Spawns a rope of `segments` links from `start` to `end`, with the first link hanging from
+/// `anchor`. Returns the last link, so something can be hung from it
+pub fn spawn_rope(
+    commands: &mut Commands,
+    anchor: JointAnchor,
+    start: Vec2,
+    end: Vec2,
+    segments: usize,
+) -> Entity {
+    let segments = segments.max(1);
+    let link_length = start.distance(end) / segments as f32;
+
+    let mut anchor = anchor;
+    let mut last = Entity::PLACEHOLDER;
+    for i in 1..=segments {
+        let position = start.lerp(end, i as f32 / segments as f32);
+
+        last = commands
+            .spawn((
+                Name::new("RopeSegment"),
+                MovingSpriteBundle {
+                    sprite_bundle: SpriteBundle {
+                        sprite: Sprite {
+                            color: ROPE_COLOR,
+                            custom_size: Some(Vec2::splat(ROPE_SEGMENT_SIZE)),
+                            ..default()
+                        },
+                        transform: Transform::from_translation(position.extend(0.0)),
+                        ..default()
+                    },
+                    gravity: Gravity::new(Vec2::NEG_Y * GRAVITY_CONSTANT, ROPE_TERMINAL_VELOCITY),
+                    aabb: AABB::new(Vec2::splat(ROPE_SEGMENT_SIZE / 2.0)),
+                    moving_object: MovingObject {
+                        mass: ROPE_SEGMENT_MASS,
+                        position: Position::new(position),
+                        old_position: Position::new(position),
+                        ..default()
+                    },
+                },
+                RopeSegment,
+                DistanceJoint::rope(anchor, link_length),
+                CollisionLayers::new(ROPE_LAYER, TILE_LAYER),
+            ))
+            .id();
+        anchor = JointAnchor::Body(last);
+    }
+    last
+}
+
+/// Despawns the rope ending in `last`, following its links up to where it hangs from
+pub fn despawn_rope(
+    commands: &mut Commands,
+    ropes: &Query<&DistanceJoint, With<RopeSegment>>,
+    last: Entity,
+) {
+    let mut link = Some(last);
+    while let Some(entity) = link {
+        let Ok(joint) = ropes.get(entity) else {
+            break;
+        };
+        commands.entity(entity).despawn();
+        link = match joint.anchor {
+            JointAnchor::Body(anchor) => Some(anchor),
+            JointAnchor::Point(_) => None,
+        };
+    }
+}
+
+// A line along every joint, so ropes look connected and the ones without links show up at all
+fn draw_joints(
+    mut gizmos: Gizmos,
+    query: Query<(&Transform, &DistanceJoint)>,
+    transforms: Query<&Transform>,
+) {
+    for (transform, joint) in &query {
+        let anchor = match joint.anchor {
+            JointAnchor::Point(point) => point,
+            JointAnchor::Body(entity) => match transforms.get(entity) {
+                Ok(transform) => transform.translation.truncate(),
+                Err(_) => continue,
+            },
+        };
+        gizmos.line_2d(transform.translation.truncate(), anchor, ROPE_COLOR);
+    }
+}
@@ -0,0 +1,359 @@
+use bevy::{math::I64Vec2, prelude::*, utils::HashMap};
+
+use crate::{
+    broadphase::{Broadphase, BroadphaseObject},
+    physics::{Position, AABB},
+};
+
+// smaller cells would make every object span a huge amount of them
+const MIN_CELL_SIZE: f32 = 1.0;
+// objects touching more cells than this are kept in a list every query checks instead
+const MAX_OBJECT_CELLS: i64 = 64;
+
+/// Splits the space into square cells of the same size. Objects are stored in every cell they
+/// touch, so queries only have to look at the cells around them
+#[derive(Debug)]
+pub struct SpatialHash {
+    cell_size: f32,
+    cells: HashMap<IVec2, Vec<BroadphaseObject>>,
+    // objects that would be stored in too many cells
+    oversized: Vec<BroadphaseObject>,
+    // the cell range each entity is stored in
+    locations: HashMap<Entity, (IVec2, IVec2)>,
+}
+impl SpatialHash {
+    pub fn new(cell_size: f32) -> Self {
+        Self {
+            cell_size: cell_size.max(MIN_CELL_SIZE),
+            cells: HashMap::new(),
+            oversized: Vec::new(),
+            locations: HashMap::new(),
+        }
+    }
+
+    fn cell(&self, point: Vec2) -> IVec2 {
+        (point / self.cell_size).floor().as_ivec2()
+    }
+
+    // The first and last cell the aabb touches
+    fn cell_range(&self, aabb: &AABB, position: Position) -> (IVec2, IVec2) {
+        (
+            self.cell(position.value - aabb.halfsize),
+            self.cell(position.value + aabb.halfsize),
+        )
+    }
+
+    // How many cells the range covers, infinite ranges saturate to the smallest and biggest cell
+    fn cell_count((min, max): (IVec2, IVec2)) -> i64 {
+        let size = max.as_i64vec2() - min.as_i64vec2() + I64Vec2::ONE;
+        size.x.saturating_mul(size.y)
+    }
+
+    // Calls `f` with the objects of every occupied cell in the range
+    fn for_each_cell<'a>(
+        &'a self,
+        range: &AABB,
+        position: Position,
+        mut f: impl FnMut(&'a [BroadphaseObject]),
+    ) {
+        let (min, max) = self.cell_range(range, position);
+
+        // big ranges cover more cells than there are occupied ones, so just check those
+        if Self::cell_count((min, max)) > self.cells.len() as i64 {
+            for (cell, objects) in &self.cells {
+                if cell.cmpge(min).all() && cell.cmple(max).all() {
+                    f(objects);
+                }
+            }
+        } else {
+            for y in min.y..=max.y {
+                for x in min.x..=max.x {
+                    if let Some(objects) = self.cells.get(&IVec2::new(x, y)) {
+                        f(objects);
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Broadphase for SpatialHash {
+    fn insert(&mut self, entity: Entity, aabb: Option<AABB>, position: Position) {
+        let (min, max) = self.cell_range(aabb.as_ref().unwrap_or(&AABB::default()), position);
+        self.locations.insert(entity, (min, max));
+
+        if Self::cell_count((min, max)) > MAX_OBJECT_CELLS {
+            self.oversized.push((entity, (aabb, position)));
+            return;
+        }
+        for y in min.y..=max.y {
+            for x in min.x..=max.x {
+                self.cells
+                    .entry(IVec2::new(x, y))
+                    .or_default()
+                    .push((entity, (aabb.clone(), position)));
+            }
+        }
+    }
+
+    fn remove(&mut self, entity: Entity) {
+        let Some((min, max)) = self.locations.remove(&entity) else {
+            return;
+        };
+
+        if Self::cell_count((min, max)) > MAX_OBJECT_CELLS {
+            self.oversized.retain(|(other, _)| *other != entity);
+            return;
+        }
+        for y in min.y..=max.y {
+            for x in min.x..=max.x {
+                let cell = IVec2::new(x, y);
+                if let Some(objects) = self.cells.get_mut(&cell) {
+                    objects.retain(|(other, _)| *other != entity);
+                    // empty cells would make big queries slower
+                    if objects.is_empty() {
+                        self.cells.remove(&cell);
+                    }
+                }
+            }
+        }
+    }
+
+    // bodies usually stay in the same cells between ticks, then they are only replaced
+    fn update(&mut self, entity: Entity, aabb: Option<AABB>, position: Position) {
+        let range = self.cell_range(aabb.as_ref().unwrap_or(&AABB::default()), position);
+        if self.locations.get(&entity) != Some(&range) {
+            self.remove(entity);
+            self.insert(entity, aabb, position);
+            return;
+        }
+
+        if Self::cell_count(range) > MAX_OBJECT_CELLS {
+            for object in self
+                .oversized
+                .iter_mut()
+                .filter(|(other, _)| *other == entity)
+            {
+                object.1 = (aabb.clone(), position);
+            }
+            return;
+        }
+        let (min, max) = range;
+        for y in min.y..=max.y {
+            for x in min.x..=max.x {
+                let Some(objects) = self.cells.get_mut(&IVec2::new(x, y)) else {
+                    continue;
+                };
+                for object in objects.iter_mut().filter(|(other, _)| *other == entity) {
+                    object.1 = (aabb.clone(), position);
+                }
+            }
+        }
+    }
+
+    fn query_candidates<'a>(
+        &'a self,
+        range: &AABB,
+        position: Position,
+        found: &mut Vec<&'a BroadphaseObject>,
+    ) {
+        self.for_each_cell(range, position, |objects| found.extend(objects));
+        found.extend(&self.oversized);
+    }
+
+    // only the occupied cells exist, oversized objects arent in any
+    fn for_each_node(&self, f: &mut dyn FnMut(Vec2, Vec2)) {
+        let halfsize = Vec2::splat(self.cell_size / 2.0);
+        for cell in self.cells.keys() {
+            f(cell.as_vec2() * self.cell_size + halfsize, halfsize);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::broadphase::touches;
+    use proptest::prelude::*;
+
+    const RANGE: f32 = 100.0;
+
+    // Coordinates on a coarse grid, so objects often share positions and lie on cell edges
+    fn coordinate() -> impl Strategy<Value = f32> {
+        prop_oneof![
+            (-10..=10).prop_map(|value| value as f32 * RANGE / 10.0),
+            -RANGE..=RANGE,
+        ]
+    }
+
+    fn position() -> impl Strategy<Value = Position> {
+        (coordinate(), coordinate()).prop_map(|(x, y)| Position::new(Vec2::new(x, y)))
+    }
+
+    // Points or aabbs of any size, big ones span more than `MAX_OBJECT_CELLS` of the small cells
+    fn object() -> impl Strategy<Value = (Option<AABB>, Position)> {
+        let aabb = prop_oneof![
+            Just(None),
+            (0.0..40.0f32, 0.0..40.0f32).prop_map(|(x, y)| Some(AABB::new(Vec2::new(x, y)))),
+        ];
+        (aabb, position())
+    }
+
+    fn cell_size() -> impl Strategy<Value = f32> {
+        prop_oneof![Just(0.0), 1.0..20.0f32]
+    }
+
+    // The entities are the indices of the objects
+    fn entity(index: usize) -> Entity {
+        Entity::from_raw(index as u32)
+    }
+
+    fn build(objects: &[(Option<AABB>, Position)], cell_size: f32) -> SpatialHash {
+        let mut spatial_hash = SpatialHash::new(cell_size);
+        for (index, (aabb, position)) in objects.iter().enumerate() {
+            spatial_hash.insert(entity(index), aabb.clone(), *position);
+        }
+        spatial_hash
+    }
+
+    fn expected(
+        objects: &[(Option<AABB>, Position)],
+        range: &AABB,
+        range_position: Position,
+    ) -> Vec<Entity> {
+        objects
+            .iter()
+            .enumerate()
+            .filter(|(_, object)| touches(range, range_position, *object))
+            .map(|(index, _)| entity(index))
+            .collect()
+    }
+
+    fn oversized(spatial_hash: &SpatialHash, index: usize) -> bool {
+        spatial_hash
+            .oversized
+            .iter()
+            .any(|(other, _)| *other == entity(index))
+    }
+
+    #[test]
+    fn objects_move_in_and_out_of_the_oversized_list() {
+        let mut spatial_hash = SpatialHash::new(1.0);
+        let small = Some(AABB::new(Vec2::ONE));
+        let big = Some(AABB::new(Vec2::splat(10.0)));
+        spatial_hash.insert(entity(0), small.clone(), Position::default());
+
+        spatial_hash.update(entity(0), big, Position::new(Vec2::splat(50.0)));
+        assert!(oversized(&spatial_hash, 0));
+        assert!(spatial_hash.cells.is_empty());
+
+        spatial_hash.update(entity(0), small, Position::new(Vec2::splat(-50.0)));
+        assert!(!oversized(&spatial_hash, 0));
+
+        let mut found = Vec::new();
+        spatial_hash.query(
+            &AABB::new(Vec2::splat(20.0)),
+            Position::new(Vec2::splat(50.0)),
+            &mut found,
+        );
+        assert!(found.is_empty());
+        spatial_hash.query_point(Vec2::splat(-50.0), &mut found);
+        assert_eq!(found, vec![entity(0)]);
+
+        spatial_hash.remove(entity(0));
+        assert!(spatial_hash.cells.is_empty());
+        assert!(spatial_hash.locations.is_empty());
+    }
+
+    proptest! {
+        #[test]
+        fn query_finds_exactly_the_overlapping_objects(
+            objects in prop::collection::vec(object(), 0..200),
+            cell_size in cell_size(),
+            range in (0.0..50.0f32, 0.0..50.0f32).prop_map(|(x, y)| AABB::new(Vec2::new(x, y))),
+            range_position in position(),
+        ) {
+            let spatial_hash = build(&objects, cell_size);
+
+            let mut found = Vec::new();
+            spatial_hash.query(&range, range_position, &mut found);
+
+            prop_assert_eq!(found, expected(&objects, &range, range_position));
+        }
+
+        #[test]
+        fn slightly_moved_objects_are_found_where_they_are_now(
+            objects in prop::collection::vec(object(), 0..200),
+            cell_size in cell_size(),
+            offsets in prop::collection::vec((-2.0..2.0f32, -2.0..2.0f32), 200),
+            range in (0.0..50.0f32, 0.0..50.0f32).prop_map(|(x, y)| AABB::new(Vec2::new(x, y))),
+            range_position in position(),
+        ) {
+            let mut spatial_hash = build(&objects, cell_size);
+
+            // most of them stay in the same cells, only their stored shape changes
+            let objects: Vec<_> = objects
+                .into_iter()
+                .zip(offsets)
+                .map(|((aabb, position), (x, y))| (aabb, Position::new(position.value + Vec2::new(x, y))))
+                .collect();
+            for (index, (aabb, position)) in objects.iter().enumerate() {
+                spatial_hash.update(entity(index), aabb.clone(), *position);
+            }
+
+            let mut found = Vec::new();
+            spatial_hash.query(&range, range_position, &mut found);
+
+            prop_assert_eq!(found, expected(&objects, &range, range_position));
+        }
+
+        #[test]
+        fn removed_objects_are_never_found(
+            objects in prop::collection::vec(object(), 0..200),
+            cell_size in cell_size(),
+            removed in prop::collection::vec(any::<prop::sample::Index>(), 0..100),
+            moved in prop::collection::vec((any::<prop::sample::Index>(), object()), 0..50),
+        ) {
+            prop_assume!(!objects.is_empty());
+            let mut spatial_hash = build(&objects, cell_size);
+            let mut objects: Vec<_> = objects.into_iter().map(Some).collect();
+
+            // resizing them moves them in and out of the oversized list
+            for (index, (aabb, position)) in moved {
+                let index = index.index(objects.len());
+                spatial_hash.update(entity(index), aabb.clone(), position);
+                objects[index] = Some((aabb, position));
+            }
+            for index in removed {
+                let index = index.index(objects.len());
+                spatial_hash.remove(entity(index));
+                objects[index] = None;
+            }
+
+            let range = AABB::new(Vec2::splat(RANGE * 2.0));
+            let mut found = Vec::new();
+            spatial_hash.query(&range, Position::default(), &mut found);
+
+            for (index, object) in objects.iter().enumerate() {
+                prop_assert_eq!(object.is_some(), found.contains(&entity(index)));
+                prop_assert_eq!(
+                    object.is_some(),
+                    spatial_hash.locations.contains_key(&entity(index))
+                );
+                if let Some((aabb, position)) = object {
+                    let mut found = Vec::new();
+                    spatial_hash.query(&aabb.clone().unwrap_or_default(), *position, &mut found);
+                    prop_assert!(found.contains(&entity(index)));
+                }
+            }
+
+            // once everything is gone no cells are left
+            for index in 0..objects.len() {
+                spatial_hash.remove(entity(index));
+            }
+            prop_assert!(spatial_hash.cells.is_empty());
+            prop_assert!(spatial_hash.oversized.is_empty());
+            prop_assert!(spatial_hash.locations.is_empty());
+        }
+    }
+}
@@ -0,0 +1,127 @@
+use bevy::{ecs::system::SystemParam, prelude::*};
+
+use crate::physics::{
+    swept_aabb, swept_slope, CollisionLayers, PhysicsBroadphase, Position, Sensor, Slope,
+    StaticBroadphase, AABB,
+};
+
+/// Which entities a spatial query can hit
+#[derive(Debug, Clone, Default)]
+pub struct SpatialQueryFilter {
+    /// The query only hits entities that interact with these layers
+    pub layers: CollisionLayers,
+    pub excluded: Vec<Entity>,
+}
+impl SpatialQueryFilter {
+    pub fn new(layers: CollisionLayers, excluded: Vec<Entity>) -> Self {
+        Self { layers, excluded }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct CastHit {
+    pub entity: Entity,
+    /// How far the ray or shape travelled before the hit
+    pub distance: f32,
+    /// Where the ray or the leading face of the shape touched the entity
+    pub point: Vec2,
+    /// The surface normal of the hit entity, pointing back towards the ray or shape
+    pub normal: Vec2,
+}
+
+/// Ray and shape casts against every entity with an `AABB` in the physics world, using the
+/// broadphases of the last physics tick.
+/// Sensors and entities the ray or shape starts inside of are never hit
+#[derive(SystemParam)]
+pub struct SpatialQuery<'w, 's> {
+    broadphase: Res<'w, PhysicsBroadphase>,
+    static_broadphase: Res<'w, StaticBroadphase>,
+    layers: Query<'w, 's, &'static CollisionLayers>,
+    aabbs: Query<'w, 's, (), With<AABB>>,
+    sensors: Query<'w, 's, (), With<Sensor>>,
+    slopes: Query<'w, 's, &'static Slope>,
+}
+impl<'w, 's> SpatialQuery<'w, 's> {
+    /// Returns the first entity hit by the ray going from `origin` towards `direction`
+    pub fn cast_ray(
+        &self,
+        origin: Vec2,
+        direction: Vec2,
+        max_distance: f32,
+        filter: &SpatialQueryFilter,
+    ) -> Option<CastHit> {
+        self.cast_shape(
+            &AABB::new(Vec2::ZERO),
+            origin,
+            direction,
+            max_distance,
+            filter,
+        )
+    }
+
+    /// Returns the first entity hit by `shape` moving from `origin` towards `direction`
+    pub fn cast_shape(
+        &self,
+        shape: &AABB,
+        origin: Vec2,
+        direction: Vec2,
+        max_distance: f32,
+        filter: &SpatialQueryFilter,
+    ) -> Option<CastHit> {
+        let direction = direction.normalize_or_zero();
+        if direction == Vec2::ZERO || max_distance <= 0.0 {
+            return None;
+        }
+        let movement = direction * max_distance;
+
+        // aabb covering the whole cast
+        let range = AABB::new(shape.halfsize + movement.abs() / 2.0);
+        let range_center = Position::new(origin + movement / 2.0);
+
+        let mut candidates = Vec::new();
+        self.broadphase
+            .0
+            .query_objects(&range, range_center, &mut candidates);
+        self.static_broadphase
+            .0
+            .query_objects(&range, range_center, &mut candidates);
+
+        candidates
+            .into_iter()
+            .filter(|(entity, _)| self.can_hit(*entity, filter))
+            .filter_map(|(entity, (aabb, position))| {
+                // skip points, they cant be hit
+                let aabb = aabb.as_ref()?;
+                let start = Position::new(origin);
+                let end = Position::new(origin + movement);
+                // slopes are only solid under their surface
+                let (time_of_impact, normal) = match self.slopes.get(*entity) {
+                    Ok(slope) => swept_slope(shape, start, end, aabb, *position, slope),
+                    Err(_) => swept_aabb(shape, start, end, aabb, *position),
+                }?;
+                Some((*entity, time_of_impact, normal))
+            })
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+            .map(|(entity, time_of_impact, normal)| {
+                let center = origin + movement * time_of_impact;
+                CastHit {
+                    entity,
+                    distance: max_distance * time_of_impact,
+                    // the middle of the face that touched, roughly the touching corner on slopes
+                    point: center - normal * shape.halfsize,
+                    normal,
+                }
+            })
+    }
+
+    // The broadphases can still hold entities that were despawned or lost their `AABB` since the
+    // last physics tick, those cant be hit
+    fn can_hit(&self, entity: Entity, filter: &SpatialQueryFilter) -> bool {
+        self.aabbs.contains(entity)
+            && !filter.excluded.contains(&entity)
+            && !self.sensors.contains(entity)
+            && filter
+                .layers
+                .interacts_with(self.layers.get(entity).copied().unwrap_or_default())
+    }
+}